@@ -0,0 +1,227 @@
+// Library for building scalable privacy-preserving microservices P2P nodes
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2022-2023 by
+//     Dr. Maxim Orlovsky <orlovsky@cyphernet.org>
+//
+// Copyright 2022-2023 Cyphernet DAO, Switzerland
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Socket activation: discovery of the listening sockets passed to the process
+//! by the service manager (like systemd) using `LISTEN_FDS`, `LISTEN_PID` and
+//! `LISTEN_FDNAMES` environment variables.
+//!
+//! The discovered descriptors can be adopted with
+//! [`crate::NetListener::from_listening_fd`] or
+//! [`crate::NetAccept::with_listening_fd`], which allows restarting the
+//! service without closing its listening sockets.
+
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{env, io};
+
+/// The first descriptor passed by the service manager; the rest follow it
+/// sequentially.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// Listening socket passed by the service manager.
+#[derive(Debug)]
+pub struct ListenFd {
+    /// Socket descriptor.
+    pub fd: OwnedFd,
+    /// Name of the socket from `LISTEN_FDNAMES`, if any (for systemd, this is
+    /// `FileDescriptorName=` of the socket unit).
+    pub name: Option<String>,
+}
+
+/// Returns listening sockets passed to the process by the service manager.
+///
+/// Returns an empty list if `LISTEN_PID` is not set or doesn't match the
+/// current process, which happens when the process was not socket-activated.
+/// Errors with [`io::ErrorKind::InvalidInput`] if the environment variables
+/// are malformed or if any of the descriptors they refer to is not an open
+/// socket; in this case none of the descriptors is taken (or closed).
+///
+/// The descriptors are taken only once per process: subsequent calls return an
+/// empty list. They are marked close-on-exec, so they are not inherited by
+/// child processes.
+///
+/// Unlike `sd_listen_fds(1)`, the function doesn't remove the environment
+/// variables, since modifying the environment is not thread-safe. Child
+/// processes ignore the inherited variables, since `LISTEN_PID` doesn't match
+/// them; if the variables still need to be removed, the caller should do it
+/// while no other threads access the environment.
+pub fn listen_fds() -> io::Result<Vec<ListenFd>> {
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+
+    let Ok(pid) = env::var("LISTEN_PID") else {
+        return Ok(vec![]);
+    };
+    let fds = env::var("LISTEN_FDS").ok();
+    let names = env::var("LISTEN_FDNAMES").ok();
+    listen_fds_from(LISTEN_FDS_START, &TAKEN, &pid, fds.as_deref(), names.as_deref())
+}
+
+/// Takes descriptors from the `start` according to the values of `LISTEN_PID`,
+/// `LISTEN_FDS` and `LISTEN_FDNAMES` variables, unless they are already
+/// `taken`.
+fn listen_fds_from(
+    start: RawFd,
+    taken: &AtomicBool,
+    pid: &str,
+    fds: Option<&str>,
+    names: Option<&str>,
+) -> io::Result<Vec<ListenFd>> {
+    let pid = pid.parse::<u32>().map_err(|_| io::ErrorKind::InvalidInput)?;
+    if pid != std::process::id() {
+        return Ok(vec![]);
+    }
+    let count = fds
+        .ok_or(io::ErrorKind::InvalidInput)?
+        .parse::<RawFd>()
+        .map_err(|_| io::ErrorKind::InvalidInput)?;
+    if !(0..=RawFd::MAX - start).contains(&count) {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    // The descriptors are checked before taking ownership of any of them, so
+    // a descriptor which is not open, or which is already used for something
+    // else than a socket, is never closed by us.
+    for raw in start..start + count {
+        check_socket(raw)?;
+    }
+    if taken.swap(true, Ordering::SeqCst) {
+        return Ok(vec![]);
+    }
+    let mut names = names.map(|names| names.split(':').map(String::from).collect::<Vec<_>>());
+
+    (start..start + count)
+        .enumerate()
+        .map(|(index, raw)| {
+            // The service manager transfers ownership of the descriptors to
+            // the process; they are taken only once, and they are checked to
+            // be open sockets.
+            let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+            set_cloexec(&fd)?;
+            let name = names
+                .as_mut()
+                .and_then(|names| names.get_mut(index))
+                .map(std::mem::take)
+                .filter(|name| !name.is_empty());
+            Ok(ListenFd { fd, name })
+        })
+        .collect()
+}
+
+fn check_socket(fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("socket activation descriptor {fd} is not open"),
+        ));
+    }
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("socket activation descriptor {fd} is not a socket"),
+        ));
+    }
+    Ok(())
+}
+
+fn set_cloexec(fd: &OwnedFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    /// Duplicates descriptors to consecutive numbers, like the service manager
+    /// passes them, returning the first one.
+    fn dup_consecutive(fds: &[RawFd]) -> RawFd {
+        let start = unsafe { libc::fcntl(fds[0], libc::F_DUPFD, 512) };
+        assert!(start >= 0);
+        for (index, fd) in fds.iter().enumerate().skip(1) {
+            let raw = unsafe { libc::fcntl(*fd, libc::F_DUPFD, start) };
+            assert_eq!(raw, start + index as RawFd);
+        }
+        start
+    }
+
+    fn is_open(fd: RawFd) -> bool { unsafe { libc::fcntl(fd, libc::F_GETFD) >= 0 } }
+
+    #[test]
+    fn listen_fds() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (a, _b) = UnixStream::pair().unwrap();
+        let start = dup_consecutive(&[listener.as_raw_fd(), a.as_raw_fd()]);
+        let pid = std::process::id().to_string();
+        let taken = AtomicBool::new(false);
+
+        // Activated for a different process
+        assert!(listen_fds_from(start, &taken, "1", Some("2"), None).unwrap().is_empty());
+
+        // Malformed variables
+        for (pid, fds) in
+            [("one", Some("2")), (&pid, None), (&pid, Some("two")), (&pid, Some("-1"))]
+        {
+            let err = listen_fds_from(start, &taken, pid, fds, None).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        // Not open descriptor following valid ones: nothing is taken
+        let err = listen_fds_from(start, &taken, &pid, Some("3"), None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(is_open(start) && is_open(start + 1));
+
+        // Not a socket
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let null = dup_consecutive(&[file.as_raw_fd()]);
+        let err = listen_fds_from(null, &taken, &pid, Some("1"), None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(is_open(null));
+        drop(unsafe { OwnedFd::from_raw_fd(null) });
+
+        let fds = listen_fds_from(start, &taken, &pid, Some("2"), Some("http:")).unwrap();
+        assert_eq!(fds.len(), 2);
+        assert_eq!(fds[0].fd.as_raw_fd(), start);
+        assert_eq!(fds[0].name.as_deref(), Some("http"));
+        assert_eq!(fds[1].fd.as_raw_fd(), start + 1);
+        assert_eq!(fds[1].name, None);
+        for fd in &fds {
+            let flags = unsafe { libc::fcntl(fd.fd.as_raw_fd(), libc::F_GETFD) };
+            assert_ne!(flags & libc::FD_CLOEXEC, 0);
+        }
+        let adopted = TcpListener::from(fds.into_iter().next().unwrap().fd);
+        assert_eq!(adopted.local_addr().unwrap(), listener.local_addr().unwrap());
+
+        // The descriptors can't be taken twice
+        assert!(listen_fds_from(start, &taken, &pid, Some("1"), None).unwrap().is_empty());
+    }
+}
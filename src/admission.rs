@@ -0,0 +1,735 @@
+// Library for building scalable privacy-preserving microservices P2P nodes
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2022-2023 by
+//     Dr. Maxim Orlovsky <orlovsky@cyphernet.org>
+//
+// Copyright 2022-2023 Cyphernet DAO, Switzerland
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Admission control for the incoming connections: filters which are consulted
+//! by [`crate::NetAccept`] before an accepted connection is handed over to the
+//! application. Rejected connections are closed immediately and reported with
+//! [`crate::ListenerEvent::Rejected`].
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cyphernet::addr::{InetHost, NetAddr};
+
+use crate::{LoopbackAddr, UnixAddr};
+
+/// Address of a remote peer which may be an IP address, which is used by the
+/// IP-based admission filters.
+pub trait IpAddress {
+    /// Returns IP address of the peer, if any. IPv4-mapped IPv6 addresses are
+    /// returned as IPv4 addresses.
+    fn ip_addr(&self) -> Option<IpAddr>;
+}
+
+impl IpAddress for IpAddr {
+    fn ip_addr(&self) -> Option<IpAddr> {
+        match self {
+            IpAddr::V6(ip) => Some(ip.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*self)),
+            IpAddr::V4(_) => Some(*self),
+        }
+    }
+}
+
+impl IpAddress for SocketAddr {
+    fn ip_addr(&self) -> Option<IpAddr> { self.ip().ip_addr() }
+}
+
+impl IpAddress for NetAddr<InetHost> {
+    fn ip_addr(&self) -> Option<IpAddr> {
+        match &self.host {
+            InetHost::Ip(ip) => ip.ip_addr(),
+            InetHost::Dns(_) => None,
+        }
+    }
+}
+
+impl IpAddress for UnixAddr {
+    fn ip_addr(&self) -> Option<IpAddr> { None }
+}
+
+impl IpAddress for LoopbackAddr {
+    fn ip_addr(&self) -> Option<IpAddr> { None }
+}
+
+/// Reason for rejecting an incoming connection.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Rejection {
+    /// remote address is not allowed to connect
+    Denied,
+
+    /// too many connections from the same address
+    TooManyConnections,
+
+    /// incoming connections rate limit is exceeded
+    RateLimited,
+
+    /// {0}
+    Other(String),
+}
+
+/// Filter deciding whether an incoming connection from the remote address `A`
+/// is admitted.
+///
+/// Filters are consulted in the order they were added to the listener; the
+/// first rejection closes the connection.
+pub trait AdmissionFilter<A>: Send {
+    /// Checks whether the connection from the remote address `addr` is
+    /// admitted. The `fd` is the descriptor of the accepted connection socket,
+    /// which the filter may inspect (but not close) to track the lifetime of
+    /// the connection.
+    fn admit(&mut self, addr: &A, fd: RawFd) -> Result<(), Rejection>;
+
+    /// Called for a connection which was admitted by this filter, but then
+    /// rejected by one of the subsequent filters, allowing to revert the
+    /// changes made by [`AdmissionFilter::admit`].
+    #[allow(unused_variables)]
+    fn rollback(&mut self, addr: &A, fd: RawFd) {}
+}
+
+impl<A, F: AdmissionFilter<A> + ?Sized> AdmissionFilter<A> for Box<F> {
+    fn admit(&mut self, addr: &A, fd: RawFd) -> Result<(), Rejection> { F::admit(self, addr, fd) }
+
+    fn rollback(&mut self, addr: &A, fd: RawFd) { F::rollback(self, addr, fd) }
+}
+
+/// Sequence of the admission filters used by a listener.
+pub struct Admission<A> {
+    filters: Vec<Box<dyn AdmissionFilter<A>>>,
+}
+
+impl<A> Default for Admission<A> {
+    fn default() -> Self { Admission { filters: vec![] } }
+}
+
+impl<A> Debug for Admission<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Admission").field("filters", &self.filters.len()).finish()
+    }
+}
+
+impl<A> Admission<A> {
+    /// Adds filter to the end of the sequence.
+    pub fn push(&mut self, filter: impl AdmissionFilter<A> + 'static) {
+        self.filters.push(Box::new(filter))
+    }
+
+    /// Checks whether there are no filters, i.e. all connections are admitted.
+    pub fn is_empty(&self) -> bool { self.filters.is_empty() }
+
+    /// Checks the connection against all the filters, rolling back the filters
+    /// which have admitted the connection if some other filter rejects it.
+    pub fn admit(&mut self, addr: &A, fd: RawFd) -> Result<(), Rejection> {
+        for index in 0..self.filters.len() {
+            if let Err(reason) = self.filters[index].admit(addr, fd) {
+                for filter in &mut self.filters[..index] {
+                    filter.rollback(addr, fd);
+                }
+                return Err(reason);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rolls back the admission of a connection by all the filters, for
+    /// instance when the connection has failed right after being admitted.
+    pub fn rollback(&mut self, addr: &A, fd: RawFd) {
+        for filter in &mut self.filters {
+            filter.rollback(addr, fd);
+        }
+    }
+}
+
+/// Range of IP addresses in CIDR notation (like `10.0.0.0/8` or `fe80::/10`).
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display("{addr}/{prefix}")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+/// Errors parsing [`Cidr`] string.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CidrError {
+    /// invalid IP address in CIDR range '{0}'
+    InvalidAddr(String),
+
+    /// invalid prefix length in CIDR range '{0}'
+    InvalidPrefix(String),
+}
+
+impl Cidr {
+    /// Constructs range from the address and prefix length. Returns `None` if
+    /// the prefix is longer than the address. IPv4-mapped IPv6 ranges are
+    /// converted into IPv4 ranges.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        match addr {
+            IpAddr::V4(_) if prefix > 32 => None,
+            IpAddr::V6(_) if prefix > 128 => None,
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) if prefix >= 96 => Some(Cidr {
+                    addr: ip.into(),
+                    prefix: prefix - 96,
+                }),
+                _ => Some(Cidr { addr, prefix }),
+            },
+            IpAddr::V4(_) => Some(Cidr { addr, prefix }),
+        }
+    }
+
+    /// Range containing a single address.
+    pub fn host(addr: IpAddr) -> Self {
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        Cidr::new(addr, prefix).expect("maximal prefix length")
+    }
+
+    pub fn addr(self) -> IpAddr { self.addr }
+
+    pub fn prefix(self) -> u8 { self.prefix }
+
+    /// Checks whether the address belongs to the range. IPv4-mapped IPv6
+    /// addresses are matched against IPv4 ranges.
+    pub fn contains(self, ip: IpAddr) -> bool {
+        let mask = |len: u32| match self.prefix {
+            0 => 0u128,
+            prefix => u128::MAX << (len - prefix as u32),
+        };
+        match (self.addr, ip.ip_addr().unwrap_or(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = mask(32) as u32;
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self { Cidr::host(addr) }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    /// Parses range in CIDR notation; a plain IP address is parsed as a range
+    /// containing just that address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|_| CidrError::InvalidAddr(s.to_owned()))?;
+        match prefix {
+            None => Ok(Cidr::host(addr)),
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .and_then(|prefix| Cidr::new(addr, prefix))
+                .ok_or_else(|| CidrError::InvalidPrefix(s.to_owned())),
+        }
+    }
+}
+
+/// Filter admitting connections by their IP address.
+///
+/// Addresses from the `deny` list are always rejected. If the `allow` list is
+/// not empty, only addresses from it are admitted. Connections which do not
+/// have an IP address (like Unix sockets) are always admitted.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct IpFilter {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Constructs filter admitting only the addresses from the `allow` list.
+    pub fn allow(allow: impl IntoIterator<Item = Cidr>) -> Self {
+        IpFilter {
+            allow: allow.into_iter().collect(),
+            deny: vec![],
+        }
+    }
+
+    /// Constructs filter rejecting the addresses from the `deny` list.
+    pub fn deny(deny: impl IntoIterator<Item = Cidr>) -> Self {
+        IpFilter {
+            allow: vec![],
+            deny: deny.into_iter().collect(),
+        }
+    }
+
+    /// Checks whether the IP address is admitted by the filter.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|net| net.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip)))
+    }
+}
+
+impl<A: IpAddress> AdmissionFilter<A> for IpFilter {
+    fn admit(&mut self, addr: &A, _fd: RawFd) -> Result<(), Rejection> {
+        match addr.ip_addr() {
+            Some(ip) if !self.is_allowed(ip) => Err(Rejection::Denied),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Filter limiting the number of concurrent connections from the same IP
+/// address. Connections which do not have an IP address are not limited.
+///
+/// Admitted connections are released automatically once they are closed: the
+/// limit keeps the descriptor of each admitted connection socket together
+/// with the socket local and remote addresses, and forgets the connections
+/// whose descriptors were closed or reused for other sockets. Connections from
+/// the same IP address are checked on each admission from it; connections from
+/// all the addresses are checked once the number of the tracked addresses
+/// doubles. A connection reset by the peer keeps its slot until the
+/// application closes its descriptor.
+///
+/// Clones of the limit share the same connections, so a clone may be kept by
+/// the application to inspect them with [`ConnectionLimit::count`].
+#[derive(Clone, Debug)]
+pub struct ConnectionLimit {
+    max: usize,
+    connections: Arc<Mutex<Connections>>,
+}
+
+/// Minimal number of the IP addresses tracked by [`ConnectionLimit`] which
+/// triggers check of the connections from all the addresses.
+const PRUNE_THRESHOLD: usize = 64;
+
+#[derive(Debug)]
+struct Connections {
+    sockets: HashMap<IpAddr, Vec<SocketId>>,
+    /// Number of the tracked addresses at which all of them are checked.
+    prune_at: usize,
+}
+
+impl Default for Connections {
+    fn default() -> Self {
+        Connections {
+            sockets: empty!(),
+            prune_at: PRUNE_THRESHOLD,
+        }
+    }
+}
+
+impl Connections {
+    /// Forgets closed connections from the IP address, returning the number of
+    /// the remaining ones.
+    fn prune(&mut self, ip: IpAddr) -> usize {
+        let Some(sockets) = self.sockets.get_mut(&ip) else {
+            return 0;
+        };
+        sockets.retain(SocketId::is_alive);
+        let count = sockets.len();
+        if count == 0 {
+            self.sockets.remove(&ip);
+        }
+        count
+    }
+
+    /// Forgets closed connections from all the IP addresses once the number of
+    /// the tracked addresses reaches the threshold.
+    fn prune_all(&mut self) {
+        if self.sockets.len() < self.prune_at {
+            return;
+        }
+        self.sockets.retain(|_, sockets| {
+            sockets.retain(SocketId::is_alive);
+            !sockets.is_empty()
+        });
+        self.prune_at = (self.sockets.len() * 2).max(PRUNE_THRESHOLD);
+    }
+}
+
+impl ConnectionLimit {
+    /// Constructs limit allowing at most `max` concurrent connections from the
+    /// same IP address.
+    pub fn new(max: usize) -> Self {
+        ConnectionLimit {
+            max,
+            connections: default!(),
+        }
+    }
+
+    pub fn max(&self) -> usize { self.max }
+
+    /// Returns number of the admitted connections from the IP address which
+    /// are not closed yet.
+    pub fn count(&self, ip: IpAddr) -> usize {
+        let ip = ip.ip_addr().unwrap_or(ip);
+        let mut connections = self.connections.lock().expect("poisoned connection list");
+        connections.prune(ip)
+    }
+}
+
+impl<A: IpAddress> AdmissionFilter<A> for ConnectionLimit {
+    fn admit(&mut self, addr: &A, fd: RawFd) -> Result<(), Rejection> {
+        let Some(ip) = addr.ip_addr() else {
+            return Ok(());
+        };
+        let mut connections = self.connections.lock().expect("poisoned connection list");
+        if connections.prune(ip) >= self.max {
+            return Err(Rejection::TooManyConnections);
+        }
+        // A socket which can't be identified is already disconnected, so it
+        // doesn't need to be counted.
+        if let Some(socket) = SocketId::with(fd) {
+            connections.prune_all();
+            connections.sockets.entry(ip).or_default().push(socket);
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self, addr: &A, fd: RawFd) {
+        let Some(ip) = addr.ip_addr() else {
+            return;
+        };
+        let mut connections = self.connections.lock().expect("poisoned connection list");
+        if let Some(sockets) = connections.sockets.get_mut(&ip) {
+            sockets.retain(|socket| socket.fd != fd);
+            if sockets.is_empty() {
+                connections.sockets.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Identity of a connection socket: its descriptor together with the raw local
+/// and remote socket addresses, which change if the descriptor gets closed and
+/// reused for another connection.
+///
+/// A socket which was reset by the peer has no remote address anymore
+/// (`getpeername` fails with `ENOTCONN`); such a socket is still considered
+/// alive as long as its descriptor is open and keeps the local address.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+struct SocketId {
+    fd: RawFd,
+    local: Vec<u8>,
+    remote: Vec<u8>,
+}
+
+type SocketNameFn =
+    unsafe extern "C" fn(libc::c_int, *mut libc::sockaddr, *mut libc::socklen_t) -> libc::c_int;
+
+impl SocketId {
+    fn with(fd: RawFd) -> Option<Self> {
+        Some(SocketId {
+            fd,
+            local: Self::name(fd, libc::getsockname)?,
+            remote: Self::name(fd, libc::getpeername)?,
+        })
+    }
+
+    fn is_alive(&self) -> bool {
+        if Self::name(self.fd, libc::getsockname).as_ref() != Some(&self.local) {
+            return false;
+        }
+        match Self::try_name(self.fd, libc::getpeername) {
+            Ok(remote) => remote == self.remote,
+            Err(err) => err.raw_os_error() == Some(libc::ENOTCONN),
+        }
+    }
+
+    fn name(fd: RawFd, f: SocketNameFn) -> Option<Vec<u8>> { Self::try_name(fd, f).ok() }
+
+    fn try_name(fd: RawFd, f: SocketNameFn) -> io::Result<Vec<u8>> {
+        let mut addr = unsafe { std::mem::zeroed::<libc::sockaddr_storage>() };
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if unsafe { f(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let len = (len as usize).min(std::mem::size_of::<libc::sockaddr_storage>());
+        let bytes = unsafe { std::slice::from_raw_parts(&addr as *const _ as *const u8, len) };
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Token bucket filter limiting the rate of the admitted connections.
+///
+/// The bucket holds up to `burst` tokens and is refilled with `rate` tokens per
+/// second; each admitted connection takes one token.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimit {
+    /// Constructs limit admitting `rate` connections per second on average,
+    /// with bursts of up to `burst` connections. The bucket is initially full.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        RateLimit {
+            rate,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Constructs limit admitting `count` connections per `period`, with bursts
+    /// of up to `count` connections.
+    pub fn per(count: u32, period: Duration) -> Self {
+        Self::new(count as f64 / period.as_secs_f64(), count)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+    }
+}
+
+impl<A> AdmissionFilter<A> for RateLimit {
+    fn admit(&mut self, _addr: &A, _fd: RawFd) -> Result<(), Rejection> {
+        self.refill();
+        if self.tokens < 1.0 {
+            return Err(Rejection::RateLimited);
+        }
+        self.tokens -= 1.0;
+        Ok(())
+    }
+
+    fn rollback(&mut self, _addr: &A, _fd: RawFd) {
+        self.tokens = (self.tokens + 1.0).min(self.burst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr { s.parse().unwrap() }
+
+    fn ip(s: &str) -> IpAddr { s.parse().unwrap() }
+
+    #[test]
+    fn cidr_parse() {
+        assert_eq!(cidr("10.0.0.0/8"), Cidr::new(ip("10.0.0.0"), 8).unwrap());
+        assert_eq!(cidr("fe80::/10"), Cidr::new(ip("fe80::"), 10).unwrap());
+        assert_eq!(cidr("10.1.2.3"), Cidr::host(ip("10.1.2.3")));
+        assert_eq!(cidr("::1").prefix(), 128);
+        assert_eq!(cidr("0.0.0.0/0").prefix(), 0);
+        assert_eq!(cidr("::ffff:10.0.0.0/104"), cidr("10.0.0.0/8"));
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn cidr_invalid() {
+        for s in ["10.0.0/8", "10.0.0.0.0/8", "host/8", "", "/8"] {
+            assert_eq!(Cidr::from_str(s), Err(CidrError::InvalidAddr(s.to_owned())));
+        }
+        for s in ["10.0.0.0/33", "::/129", "10.0.0.0/", "10.0.0.0/-1", "10.0.0.0/8/8", "::/256"] {
+            assert_eq!(Cidr::from_str(s), Err(CidrError::InvalidPrefix(s.to_owned())));
+        }
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let net = cidr("192.168.0.0/16");
+        assert!(net.contains(ip("192.168.0.0")));
+        assert!(net.contains(ip("192.168.255.255")));
+        assert!(!net.contains(ip("192.169.0.0")));
+        assert!(net.contains(ip("::ffff:192.168.1.1")));
+        assert!(!net.contains(ip("::1")));
+
+        let net = cidr("2001:db8::/32");
+        assert!(net.contains(ip("2001:db8:ffff::1")));
+        assert!(!net.contains(ip("2001:db9::")));
+        assert!(!net.contains(ip("1.2.3.4")));
+
+        assert!(cidr("0.0.0.0/0").contains(Ipv4Addr::BROADCAST.into()));
+        assert!(!cidr("0.0.0.0/0").contains(Ipv6Addr::LOCALHOST.into()));
+        assert!(cidr("::/0").contains(Ipv6Addr::LOCALHOST.into()));
+        assert!(cidr("10.0.0.1").contains(ip("10.0.0.1")));
+        assert!(!cidr("10.0.0.1").contains(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn ip_filter() {
+        let mut filter = IpFilter::allow([cidr("10.0.0.0/8")]);
+        filter.deny.push(cidr("10.0.0.0/24"));
+        assert!(filter.is_allowed(ip("10.1.0.1")));
+        assert!(!filter.is_allowed(ip("10.0.0.1")));
+        assert!(!filter.is_allowed(ip("11.0.0.1")));
+        assert!(IpFilter::deny([cidr("10.0.0.0/8")]).is_allowed(ip("11.0.0.1")));
+        assert!(IpFilter::default().is_allowed(ip("::1")));
+
+        let addr = SocketAddr::from(([10, 0, 0, 1], 8080));
+        assert_eq!(filter.admit(&addr, -1), Err(Rejection::Denied));
+        assert_eq!(filter.admit(&UnixAddr::Unnamed, -1), Ok(()));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let addr = SocketAddr::from(([10, 0, 0, 1], 8080));
+        let mut limit = RateLimit::per(2, Duration::from_secs(3600));
+        assert_eq!(limit.admit(&addr, -1), Ok(()));
+        assert_eq!(limit.admit(&addr, -1), Ok(()));
+        assert_eq!(limit.admit(&addr, -1), Err(Rejection::RateLimited));
+        limit.rollback(&addr, -1);
+        assert_eq!(limit.admit(&addr, -1), Ok(()));
+    }
+
+    /// Returns accepted connections along with their remote addresses.
+    fn connections(count: usize) -> Vec<(TcpStream, TcpStream)> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        (0..count)
+            .map(|_| {
+                let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+                let (accepted, _) = listener.accept().unwrap();
+                (accepted, client)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn connection_limit() {
+        let mut limit = ConnectionLimit::new(2);
+        let observer = limit.clone();
+        let mut connections = connections(3);
+        let localhost = ip("127.0.0.1");
+
+        for (accepted, _) in &connections[..2] {
+            let addr = accepted.peer_addr().unwrap();
+            assert_eq!(limit.admit(&addr, accepted.as_raw_fd()), Ok(()));
+        }
+        assert_eq!(observer.count(localhost), 2);
+        let (accepted, _) = &connections[2];
+        let addr = accepted.peer_addr().unwrap();
+        assert_eq!(limit.admit(&addr, accepted.as_raw_fd()), Err(Rejection::TooManyConnections));
+
+        // Closed connections are released without notice
+        drop(connections.remove(0));
+        assert_eq!(observer.count(localhost), 1);
+        let (accepted, _) = &connections[1];
+        assert_eq!(limit.admit(&addr, accepted.as_raw_fd()), Ok(()));
+        assert_eq!(observer.count(localhost), 2);
+
+        limit.rollback(&addr, accepted.as_raw_fd());
+        assert_eq!(observer.count(localhost), 1);
+        assert_eq!(observer.count(ip("::ffff:127.0.0.1")), 1);
+        assert_eq!(observer.count(ip("10.0.0.1")), 0);
+
+        // Connections without IP address are not limited
+        assert_eq!(limit.admit(&UnixAddr::Unnamed, -1), Ok(()));
+    }
+
+    #[test]
+    fn connection_limit_reused_fd() {
+        let mut limit = ConnectionLimit::new(1);
+        let mut connections = connections(2);
+        let (accepted, _) = connections.remove(0);
+        let addr = accepted.peer_addr().unwrap();
+        assert_eq!(limit.admit(&addr, accepted.as_raw_fd()), Ok(()));
+
+        // The descriptor of the connection is closed and atomically reused
+        // for another one
+        let fd = accepted.as_raw_fd();
+        let (other, _) = &connections[0];
+        assert_eq!(unsafe { libc::dup2(other.as_raw_fd(), fd) }, fd);
+        assert_eq!(limit.count(addr.ip()), 0);
+    }
+
+    #[test]
+    fn connection_limit_reset() {
+        let mut limit = ConnectionLimit::new(1);
+        let (mut accepted, client) = connections(1).remove(0);
+        let addr = accepted.peer_addr().unwrap();
+        assert_eq!(limit.admit(&addr, accepted.as_raw_fd()), Ok(()));
+
+        // The peer resets the connection, which is still open by us
+        let linger = libc::linger {
+            l_onoff: 1,
+            l_linger: 0,
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                client.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_LINGER,
+                &linger as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::linger>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(res, 0);
+        drop(client);
+        assert!(accepted.read(&mut [0u8; 1]).is_err());
+        assert!(accepted.peer_addr().is_err());
+        assert_eq!(limit.count(addr.ip()), 1);
+
+        drop(accepted);
+        assert_eq!(limit.count(addr.ip()), 0);
+    }
+
+    #[test]
+    fn connection_limit_prune_all() {
+        let mut limit = ConnectionLimit::new(1);
+        let mut connections = connections(PRUNE_THRESHOLD + 1);
+        let (last, _) = connections.pop().unwrap();
+        for (no, (accepted, _)) in connections.iter().enumerate() {
+            let addr = SocketAddr::from(([10, 0, 0, no as u8], 8080));
+            assert_eq!(limit.admit(&addr, accepted.as_raw_fd()), Ok(()));
+        }
+
+        // Connections from all the addresses are closed and forgotten on the
+        // next admission, even though it comes from a different address
+        drop(connections);
+        let addr = SocketAddr::from(([10, 0, 1, 0], 8080));
+        assert_eq!(limit.admit(&addr, last.as_raw_fd()), Ok(()));
+        let connections = limit.connections.lock().unwrap();
+        assert_eq!(connections.sockets.len(), 1);
+        assert_eq!(connections.prune_at, PRUNE_THRESHOLD);
+    }
+
+    #[test]
+    fn admission_rollback() {
+        let limit = ConnectionLimit::new(1);
+        let mut admission = Admission::default();
+        admission.push(limit.clone());
+        admission.push(IpFilter::deny([cidr("127.0.0.0/8")]));
+
+        let connections = connections(1);
+        let (accepted, _) = &connections[0];
+        let addr = accepted.peer_addr().unwrap();
+        assert_eq!(admission.admit(&addr, accepted.as_raw_fd()), Err(Rejection::Denied));
+        assert_eq!(limit.count(addr.ip()), 0);
+    }
+}
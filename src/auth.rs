@@ -0,0 +1,157 @@
+// Library for building scalable privacy-preserving microservices P2P nodes
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2022-2023 by
+//     Dr. Maxim Orlovsky <orlovsky@cyphernet.org>
+//
+// Copyright 2022-2023 Cyphernet DAO, Switzerland
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authorization of remote peers authenticated by Eidolon sessions.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::{Arc, RwLock};
+
+/// Policy deciding whether a remote peer, which has proven possession of the
+/// identity key during the session handshake, is allowed to connect.
+///
+/// The policy is queried once per session, when the handshake completes; if
+/// the peer is not authorized the handshake fails with
+/// [`cyphernet::auth::eidolon::Error::Unauthorized`] error containing the
+/// rejected key.
+///
+/// All the allowlist implementations provided by the crate ([`Vec`],
+/// [`HashSet`] and [`SharedAllowlist`]) authorize only the ids they contain;
+/// thus an empty allowlist rejects all remote peers. Use [`AllowAll`] to
+/// explicitly opt out of the authorization.
+///
+/// NB: Constructors taking a list of allowed ids rather than an authorizer
+/// (`EidolonRuntime::initiator`, `EidolonRuntime::responder`, `CypherSession`
+/// constructors and `CypherSessionBuilder::allowed_ids`) keep their historic
+/// meaning, where an empty list allows any remote peer.
+pub trait PeerAuthorizer<Pk>: Send {
+    /// Checks whether the remote peer with the identity key `id` is allowed to
+    /// connect.
+    fn is_authorized(&self, id: &Pk) -> bool;
+}
+
+impl<Pk, A: PeerAuthorizer<Pk> + ?Sized> PeerAuthorizer<Pk> for Box<A> {
+    fn is_authorized(&self, id: &Pk) -> bool { A::is_authorized(self, id) }
+}
+
+/// Authorizer allowing any remote peer.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct AllowAll;
+
+impl<Pk> PeerAuthorizer<Pk> for AllowAll {
+    fn is_authorized(&self, _id: &Pk) -> bool { true }
+}
+
+/// List of allowed ids. An empty list rejects all remote peers.
+impl<Pk: Eq + Send> PeerAuthorizer<Pk> for Vec<Pk> {
+    fn is_authorized(&self, id: &Pk) -> bool { self.contains(id) }
+}
+
+/// Static set of allowed ids. An empty set rejects all remote peers.
+impl<Pk: Eq + Hash + Send> PeerAuthorizer<Pk> for HashSet<Pk> {
+    fn is_authorized(&self, id: &Pk) -> bool { self.contains(id) }
+}
+
+/// Set of allowed ids which is shared between sessions and can be updated at
+/// runtime. Updates apply to all handshakes which have not completed yet.
+///
+/// Clones of the allowlist refer to the same set of ids. An empty set rejects
+/// all remote peers.
+#[derive(Debug, Default)]
+pub struct SharedAllowlist<Pk>(Arc<RwLock<HashSet<Pk>>>);
+
+impl<Pk> Clone for SharedAllowlist<Pk> {
+    fn clone(&self) -> Self { SharedAllowlist(self.0.clone()) }
+}
+
+impl<Pk: Eq + Hash> FromIterator<Pk> for SharedAllowlist<Pk> {
+    fn from_iter<T: IntoIterator<Item = Pk>>(iter: T) -> Self {
+        SharedAllowlist(Arc::new(RwLock::new(iter.into_iter().collect())))
+    }
+}
+
+impl<Pk: Eq + Hash> SharedAllowlist<Pk> {
+    /// Constructs an empty allowlist.
+    pub fn new() -> Self { SharedAllowlist(default!()) }
+
+    /// Adds id to the allowlist. Returns whether the id was not present before.
+    pub fn insert(&self, id: Pk) -> bool {
+        self.0.write().expect("poisoned allowlist lock").insert(id)
+    }
+
+    /// Removes id from the allowlist. Returns whether the id was present.
+    pub fn remove(&self, id: &Pk) -> bool {
+        self.0.write().expect("poisoned allowlist lock").remove(id)
+    }
+
+    /// Replaces all ids in the allowlist.
+    pub fn replace(&self, ids: impl IntoIterator<Item = Pk>) {
+        *self.0.write().expect("poisoned allowlist lock") = ids.into_iter().collect();
+    }
+
+    /// Checks whether the id is present in the allowlist.
+    pub fn contains(&self, id: &Pk) -> bool {
+        self.0.read().expect("poisoned allowlist lock").contains(id)
+    }
+
+    /// Returns number of ids in the allowlist.
+    pub fn len(&self) -> usize { self.0.read().expect("poisoned allowlist lock").len() }
+
+    /// Checks whether the allowlist is empty.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+impl<Pk: Eq + Hash + Send + Sync> PeerAuthorizer<Pk> for SharedAllowlist<Pk> {
+    fn is_authorized(&self, id: &Pk) -> bool { self.contains(id) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_rejects() {
+        assert!(!PeerAuthorizer::<u8>::is_authorized(&Vec::<u8>::new(), &1));
+        assert!(!PeerAuthorizer::<u8>::is_authorized(&HashSet::<u8>::new(), &1));
+        assert!(!SharedAllowlist::<u8>::new().is_authorized(&1));
+        assert!(PeerAuthorizer::<u8>::is_authorized(&AllowAll, &1));
+    }
+
+    #[test]
+    fn allowlists() {
+        assert!(vec![1u8, 2].is_authorized(&2));
+        assert!(!vec![1u8, 2].is_authorized(&3));
+        assert!(HashSet::from([1u8, 2]).is_authorized(&2));
+        assert!(!HashSet::from([1u8, 2]).is_authorized(&3));
+    }
+
+    #[test]
+    fn shared_updates() {
+        let allowlist = SharedAllowlist::new();
+        let authorizer: Box<dyn PeerAuthorizer<u8>> = Box::new(allowlist.clone());
+        assert!(!authorizer.is_authorized(&1));
+        allowlist.insert(1);
+        assert!(authorizer.is_authorized(&1));
+        allowlist.replace([2]);
+        assert!(!authorizer.is_authorized(&1));
+        assert!(authorizer.is_authorized(&2));
+    }
+}
@@ -19,19 +19,79 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fmt::{Debug, Display};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::hash::Hash;
 use std::io;
 use std::net::{Shutdown, TcpStream, ToSocketAddrs};
 use std::os::fd::IntoRawFd;
 use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{self, UnixStream};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use cyphernet::addr::{Addr, InetHost, NetAddr};
+use cyphernet::addr::{Addr, Host, InetHost, NetAddr};
 
 pub trait Address: Addr + Send + Clone + Eq + Hash + Debug + Display {}
 impl<T> Address for T where T: Addr + Send + Clone + Eq + Hash + Debug + Display {}
 
+/// Address of a Unix domain socket.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum UnixAddr {
+    /// Socket bound to a filesystem path.
+    Path(PathBuf),
+    /// Socket which is not bound to any address, like the client side of a
+    /// connection or a socket created with `socketpair`.
+    Unnamed,
+}
+
+impl UnixAddr {
+    pub fn as_pathname(&self) -> Option<&Path> {
+        match self {
+            UnixAddr::Path(path) => Some(path),
+            UnixAddr::Unnamed => None,
+        }
+    }
+
+    fn expect_pathname(&self) -> io::Result<&Path> {
+        self.as_pathname().ok_or_else(|| io::ErrorKind::AddrNotAvailable.into())
+    }
+}
+
+impl Display for UnixAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UnixAddr::Path(path) => write!(f, "unix:{}", path.display()),
+            UnixAddr::Unnamed => f.write_str("unix:<unnamed>"),
+        }
+    }
+}
+
+impl Host for UnixAddr {
+    fn requires_proxy(&self) -> bool { false }
+}
+
+impl Addr for UnixAddr {
+    /// Unix domain sockets have no ports; always returns zero.
+    fn port(&self) -> u16 { 0 }
+}
+
+impl From<PathBuf> for UnixAddr {
+    fn from(path: PathBuf) -> Self { UnixAddr::Path(path) }
+}
+
+impl From<&Path> for UnixAddr {
+    fn from(path: &Path) -> Self { UnixAddr::Path(path.to_path_buf()) }
+}
+
+impl From<net::SocketAddr> for UnixAddr {
+    fn from(addr: net::SocketAddr) -> Self {
+        match addr.as_pathname() {
+            Some(path) => UnixAddr::Path(path.to_path_buf()),
+            None => UnixAddr::Unnamed,
+        }
+    }
+}
+
 pub trait NetStream: Send + io::Read + io::Write {}
 
 pub trait AsConnection {
@@ -297,3 +357,106 @@ impl NetConnection for socket2::Socket {
 
     fn take_error(&self) -> io::Result<Option<io::Error>> { socket2::Socket::take_error(self) }
 }
+
+impl NetStream for UnixStream {}
+impl NetConnection for UnixStream {
+    type Addr = UnixAddr;
+
+    /// Connects to a Unix domain socket. Since the connection is local, the
+    /// `timeout` is ignored.
+    fn connect_blocking(addr: Self::Addr, _timeout: Duration) -> io::Result<Self> {
+        UnixStream::connect(addr.expect_pathname()?)
+    }
+
+    #[cfg(feature = "nonblocking")]
+    fn connect_nonblocking(addr: Self::Addr, _timeout: Duration) -> io::Result<Self> {
+        let socket = socket2::Socket::new(socket2::Domain::UNIX, socket2::Type::STREAM, None)?;
+        socket.set_nonblocking(true)?;
+        connect_unix_nonblocking(&socket, addr)?;
+        Ok(socket.into())
+    }
+
+    #[cfg(feature = "nonblocking")]
+    fn connect_reusable_nonblocking(
+        local_addr: Self::Addr,
+        remote_addr: Self::Addr,
+    ) -> io::Result<Self> {
+        let socket = socket2::Socket::new(socket2::Domain::UNIX, socket2::Type::STREAM, None)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&socket2::SockAddr::unix(local_addr.expect_pathname()?)?)?;
+        connect_unix_nonblocking(&socket, remote_addr)?;
+        Ok(socket.into())
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> { UnixStream::shutdown(self, how) }
+
+    fn remote_addr(&self) -> io::Result<Self::Addr> { Ok(UnixStream::peer_addr(self)?.into()) }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> { Ok(UnixStream::local_addr(self)?.into()) }
+
+    /// Unix domain sockets do not support TCP keepalive; always errors with
+    /// [`io::ErrorKind::Unsupported`].
+    #[cfg(feature = "nonblocking")]
+    fn set_tcp_keepalive(&mut self, _keepalive: &socket2::TcpKeepalive) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, dur)
+    }
+    fn set_write_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, dur)
+    }
+    fn read_timeout(&self) -> io::Result<Option<Duration>> { UnixStream::read_timeout(self) }
+    fn write_timeout(&self) -> io::Result<Option<Duration>> { UnixStream::write_timeout(self) }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = unsafe {
+            libc::recv(
+                self.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                libc::MSG_PEEK,
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(len as usize)
+    }
+
+    /// Unix domain sockets do not buffer small writes, so this is a no-op.
+    fn set_nodelay(&mut self, _nodelay: bool) -> io::Result<()> { Ok(()) }
+    fn nodelay(&self) -> io::Result<bool> { Ok(true) }
+    /// Unix domain sockets have no IP TTL; always errors with
+    /// [`io::ErrorKind::Unsupported`].
+    fn set_ttl(&mut self, _ttl: u32) -> io::Result<()> { Err(io::ErrorKind::Unsupported.into()) }
+    /// Unix domain sockets have no IP TTL; always errors with
+    /// [`io::ErrorKind::Unsupported`].
+    fn ttl(&self) -> io::Result<u32> { Err(io::ErrorKind::Unsupported.into()) }
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn try_clone(&self) -> io::Result<UnixStream> { UnixStream::try_clone(self) }
+    fn take_error(&self) -> io::Result<Option<io::Error>> { UnixStream::take_error(self) }
+}
+
+#[cfg(feature = "nonblocking")]
+fn connect_unix_nonblocking(socket: &socket2::Socket, addr: UnixAddr) -> io::Result<()> {
+    match socket.connect(&socket2::SockAddr::unix(addr.expect_pathname()?)?) {
+        Ok(()) => {
+            #[cfg(feature = "log")]
+            log::debug!(target: "netservices", "Connected to {}", addr);
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {
+            #[cfg(feature = "log")]
+            log::debug!(target: "netservices", "Connecting to {} in a non-blocking way", addr);
+        }
+        Err(e) => {
+            #[cfg(feature = "log")]
+            log::debug!(target: "netservices", "Error connecting to {}: {}", addr, e);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
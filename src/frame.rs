@@ -20,7 +20,11 @@
 // limitations under the License.
 
 use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::error;
+use std::fmt::{self, Debug, Formatter};
 use std::io::{self, Read, Write};
+use std::marker::PhantomData;
 
 pub trait Frame: Send + Sized {
     type Error: std::error::Error + Send;
@@ -53,10 +57,26 @@ impl Marshaller {
         }
     }
 
+    /// Marshalls frame into the write queue.
+    ///
+    /// # Panics
+    ///
+    /// If the frame can't be marshalled (for instance, it exceeds the maximum
+    /// frame size). Use [`Marshaller::try_push`] for frames which may fail.
     pub fn push<F: Frame>(&mut self, frame: F) {
         frame.marshall(&mut self.write_queue).expect("in-memory write operation");
     }
 
+    /// Marshalls frame into the write queue, leaving the queue unmodified if
+    /// the frame can't be marshalled (for instance, it exceeds the maximum
+    /// frame size).
+    pub fn try_push<F: Frame>(&mut self, frame: F) -> Result<(), F::Error> {
+        let mut buf = Vec::new();
+        frame.marshall(&mut buf)?;
+        self.write_queue.extend(buf);
+        Ok(())
+    }
+
     pub fn pop<F: Frame>(&mut self) -> Result<Option<F>, F::Error> {
         let slice = self.read_queue.make_contiguous();
         let mut cursor = io::Cursor::new(slice);
@@ -95,3 +115,386 @@ impl Write for Marshaller {
         Ok(())
     }
 }
+
+/// Maximum amount of memory allocated for a frame payload before its data are
+/// actually received.
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Payload which can be carried by [`LengthPrefixed`] frames.
+pub trait FramePayload: Send + Sized {
+    type Error: error::Error + Send;
+
+    /// Constructs payload from the frame data.
+    fn from_frame_data(data: Vec<u8>) -> Result<Self, Self::Error>;
+    /// Serializes payload into the frame data.
+    fn to_frame_data(&self) -> Vec<u8>;
+}
+
+impl FramePayload for Vec<u8> {
+    type Error = Infallible;
+
+    fn from_frame_data(data: Vec<u8>) -> Result<Self, Self::Error> { Ok(data) }
+    fn to_frame_data(&self) -> Vec<u8> { self.clone() }
+}
+
+/// Encoding of the frame length prefix used by [`LengthPrefixed`] frames.
+pub trait LengthPrefix: Send {
+    /// Maximum frame length which can be represented by the prefix.
+    const MAX_LEN: usize;
+
+    /// Reads length prefix from the stream.
+    ///
+    /// # Errors
+    ///
+    /// With [`io::ErrorKind::UnexpectedEof`] if the stream doesn't contain the
+    /// whole prefix yet, and with [`io::ErrorKind::InvalidData`] if the prefix
+    /// is not correctly encoded.
+    fn read_len(reader: &mut impl Read) -> io::Result<usize>;
+
+    /// Writes length prefix to the stream, returning number of bytes written.
+    fn write_len(len: usize, writer: &mut impl Write) -> io::Result<usize>;
+}
+
+/// Two-byte big-endian length prefix.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct U16Prefix;
+
+impl LengthPrefix for U16Prefix {
+    const MAX_LEN: usize = u16::MAX as usize;
+
+    fn read_len(reader: &mut impl Read) -> io::Result<usize> {
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf)?;
+        Ok(u16::from_be_bytes(buf) as usize)
+    }
+
+    fn write_len(len: usize, writer: &mut impl Write) -> io::Result<usize> {
+        let len = u16::try_from(len).map_err(|_| io::ErrorKind::InvalidInput)?;
+        writer.write_all(&len.to_be_bytes())?;
+        Ok(2)
+    }
+}
+
+/// Four-byte big-endian length prefix.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct U32Prefix;
+
+impl LengthPrefix for U32Prefix {
+    const MAX_LEN: usize = u32::MAX as usize;
+
+    fn read_len(reader: &mut impl Read) -> io::Result<usize> {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf) as usize)
+    }
+
+    fn write_len(len: usize, writer: &mut impl Write) -> io::Result<usize> {
+        let len = u32::try_from(len).map_err(|_| io::ErrorKind::InvalidInput)?;
+        writer.write_all(&len.to_be_bytes())?;
+        Ok(4)
+    }
+}
+
+/// Variable-length (unsigned LEB128) length prefix, taking from one to ten
+/// bytes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct VarIntPrefix;
+
+impl LengthPrefix for VarIntPrefix {
+    const MAX_LEN: usize = usize::MAX;
+
+    fn read_len(reader: &mut impl Read) -> io::Result<usize> {
+        let mut len = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8; 1];
+            reader.read_exact(&mut byte)?;
+            let bits = (byte[0] & 0x7F) as u64;
+            if shift == 63 && bits > 1 {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            len |= bits << shift;
+            if byte[0] & 0x80 == 0 {
+                return usize::try_from(len).map_err(|_| io::ErrorKind::InvalidData.into());
+            }
+        }
+        Err(io::ErrorKind::InvalidData.into())
+    }
+
+    fn write_len(len: usize, writer: &mut impl Write) -> io::Result<usize> {
+        let mut len = len as u64;
+        let mut count = 0usize;
+        loop {
+            let mut byte = (len & 0x7F) as u8;
+            len >>= 7;
+            if len > 0 {
+                byte |= 0x80;
+            }
+            writer.write_all(&[byte])?;
+            count += 1;
+            if len == 0 {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+/// Errors of [`LengthPrefixed`] frame encoding and decoding.
+#[derive(Debug, Display)]
+#[display(doc_comments)]
+pub enum FrameError<E: error::Error> {
+    /// frame size of {announced} bytes exceeds maximum allowed frame size of
+    /// {max} bytes
+    Oversized { announced: usize, max: usize },
+
+    /// invalid frame length prefix
+    InvalidPrefix,
+
+    /// I/O error during frame serialization. Details: {0}
+    Io(io::Error),
+
+    /// invalid frame payload. Details: {0}
+    Payload(E),
+}
+
+impl<E: error::Error + 'static> error::Error for FrameError<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FrameError::Oversized { .. } | FrameError::InvalidPrefix => None,
+            FrameError::Io(err) => Some(err),
+            FrameError::Payload(err) => Some(err),
+        }
+    }
+}
+
+/// Generic length-prefixed frame wrapping a payload of type `P`.
+///
+/// The frame is serialized as a length prefix (encoded according to `L`)
+/// followed by the payload data. Frames with payload exceeding `MAX_LEN`
+/// bytes are rejected both during marshalling and unmarshalling: once a peer
+/// announces an oversized frame, [`Marshaller::pop`] fails with
+/// [`FrameError::Oversized`] without waiting for (and buffering) the rest of
+/// the frame data.
+pub struct LengthPrefixed<
+    P: FramePayload,
+    L: LengthPrefix = U32Prefix,
+    const MAX_LEN: usize = { u16::MAX as usize },
+> {
+    payload: P,
+    _phantom: PhantomData<L>,
+}
+
+impl<P: FramePayload, L: LengthPrefix, const MAX_LEN: usize> LengthPrefixed<P, L, MAX_LEN> {
+    /// Maximum allowed frame payload size, which is the minimum of the
+    /// `MAX_LEN` and maximum length representable by the prefix `L`.
+    pub const MAX_FRAME_SIZE: usize = if MAX_LEN < L::MAX_LEN { MAX_LEN } else { L::MAX_LEN };
+
+    pub fn new(payload: P) -> Self {
+        Self {
+            payload,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn as_payload(&self) -> &P { &self.payload }
+    pub fn into_payload(self) -> P { self.payload }
+}
+
+impl<P: FramePayload, L: LengthPrefix, const MAX_LEN: usize> From<P>
+    for LengthPrefixed<P, L, MAX_LEN>
+{
+    fn from(payload: P) -> Self { Self::new(payload) }
+}
+
+impl<P: FramePayload + Clone, L: LengthPrefix, const MAX_LEN: usize> Clone
+    for LengthPrefixed<P, L, MAX_LEN>
+{
+    fn clone(&self) -> Self { Self::new(self.payload.clone()) }
+}
+
+impl<P: FramePayload + PartialEq, L: LengthPrefix, const MAX_LEN: usize> PartialEq
+    for LengthPrefixed<P, L, MAX_LEN>
+{
+    fn eq(&self, other: &Self) -> bool { self.payload == other.payload }
+}
+
+impl<P: FramePayload + Eq, L: LengthPrefix, const MAX_LEN: usize> Eq
+    for LengthPrefixed<P, L, MAX_LEN>
+{
+}
+
+impl<P: FramePayload + Debug, L: LengthPrefix, const MAX_LEN: usize> Debug
+    for LengthPrefixed<P, L, MAX_LEN>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("LengthPrefixed").field(&self.payload).finish()
+    }
+}
+
+impl<P: FramePayload, L: LengthPrefix, const MAX_LEN: usize> Frame for LengthPrefixed<P, L, MAX_LEN>
+where P::Error: 'static
+{
+    type Error = FrameError<P::Error>;
+
+    fn unmarshall(mut reader: impl Read) -> Result<Option<Self>, Self::Error> {
+        let len = match L::read_len(&mut reader) {
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(_) => return Err(FrameError::InvalidPrefix),
+        };
+        if len > Self::MAX_FRAME_SIZE {
+            return Err(FrameError::Oversized {
+                announced: len,
+                max: Self::MAX_FRAME_SIZE,
+            });
+        }
+        // The buffer grows with the data actually available, so a peer can't
+        // make us allocate the whole announced length upfront.
+        let mut data = Vec::with_capacity(len.min(READ_CHUNK_SIZE));
+        reader.take(len as u64).read_to_end(&mut data).map_err(FrameError::Io)?;
+        if data.len() < len {
+            return Ok(None);
+        }
+        P::from_frame_data(data).map(Self::new).map(Some).map_err(FrameError::Payload)
+    }
+
+    fn marshall(&self, mut writer: impl Write) -> Result<usize, Self::Error> {
+        let data = self.payload.to_frame_data();
+        if data.len() > Self::MAX_FRAME_SIZE {
+            return Err(FrameError::Oversized {
+                announced: data.len(),
+                max: Self::MAX_FRAME_SIZE,
+            });
+        }
+        let prefix_len = L::write_len(data.len(), &mut writer).map_err(FrameError::Io)?;
+        writer.write_all(&data).map_err(FrameError::Io)?;
+        Ok(prefix_len + data.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type U16Frame = LengthPrefixed<Vec<u8>, U16Prefix>;
+    type U32Frame = LengthPrefixed<Vec<u8>, U32Prefix, 16>;
+    type VarIntFrame = LengthPrefixed<Vec<u8>, VarIntPrefix, 1024>;
+
+    fn encode<F: Frame>(frame: &F) -> Vec<u8> {
+        let mut buf = vec![];
+        frame.marshall(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn u16_roundtrip() {
+        let frame = U16Frame::new(b"hello".to_vec());
+        let data = encode(&frame);
+        assert_eq!(data, b"\x00\x05hello");
+        assert_eq!(U16Frame::unmarshall(&data[..]).unwrap(), Some(frame));
+    }
+
+    #[test]
+    fn u32_roundtrip() {
+        let frame = U32Frame::new(b"hello".to_vec());
+        let data = encode(&frame);
+        assert_eq!(data, b"\x00\x00\x00\x05hello");
+        assert_eq!(U32Frame::unmarshall(&data[..]).unwrap(), Some(frame));
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        for (len, prefix) in
+            [(0usize, &[0x00][..]), (127, &[0x7F]), (128, &[0x80, 0x01]), (300, &[0xAC, 0x02])]
+        {
+            let frame = VarIntFrame::new(vec![0xAA; len]);
+            let data = encode(&frame);
+            assert_eq!(&data[..prefix.len()], prefix);
+            assert_eq!(data.len(), prefix.len() + len);
+            assert_eq!(VarIntFrame::unmarshall(&data[..]).unwrap(), Some(frame));
+        }
+    }
+
+    #[test]
+    fn varint_max_len() {
+        let mut buf = vec![];
+        assert_eq!(VarIntPrefix::write_len(usize::MAX, &mut buf).unwrap(), 10);
+        assert_eq!(VarIntPrefix::read_len(&mut &buf[..]).unwrap(), usize::MAX);
+    }
+
+    #[test]
+    fn varint_overlong() {
+        let data = [0xFF; 11];
+        assert!(matches!(VarIntFrame::unmarshall(&data[..]), Err(FrameError::InvalidPrefix)));
+        let data = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02];
+        assert!(matches!(VarIntFrame::unmarshall(&data[..]), Err(FrameError::InvalidPrefix)));
+    }
+
+    #[test]
+    fn truncated_prefix() {
+        assert_eq!(U16Frame::unmarshall(&b""[..]).unwrap(), None);
+        assert_eq!(U16Frame::unmarshall(&b"\x00"[..]).unwrap(), None);
+        assert_eq!(U32Frame::unmarshall(&b"\x00\x00\x00"[..]).unwrap(), None);
+        assert_eq!(VarIntFrame::unmarshall(&b"\x80"[..]).unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_payload() {
+        assert_eq!(U16Frame::unmarshall(&b"\x00\x05hell"[..]).unwrap(), None);
+        assert_eq!(VarIntFrame::unmarshall(&b"\x05"[..]).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_announced() {
+        let err = U32Frame::unmarshall(&b"\x00\x00\x00\x11"[..]).unwrap_err();
+        assert!(matches!(err, FrameError::Oversized {
+            announced: 17,
+            max: 16
+        }));
+        // Must fail without waiting for the frame data
+        let err = VarIntFrame::unmarshall(&b"\xFF\xFF\xFF\xFF\x0F"[..]).unwrap_err();
+        assert!(matches!(err, FrameError::Oversized { max: 1024, .. }));
+    }
+
+    #[test]
+    fn oversized_marshall() {
+        let frame = U32Frame::new(vec![0; 17]);
+        assert!(matches!(
+            frame.marshall(vec![]),
+            Err(FrameError::Oversized {
+                announced: 17,
+                max: 16
+            })
+        ));
+
+        let mut marshaller = Marshaller::new();
+        let err = marshaller.try_push(U32Frame::new(vec![0; 17])).unwrap_err();
+        assert!(matches!(err, FrameError::Oversized { .. }));
+        assert_eq!(marshaller.queue_len(), 0);
+
+        let err = marshaller.try_push(U16Frame::new(vec![0; u16::MAX as usize + 1])).unwrap_err();
+        assert!(matches!(err, FrameError::Oversized { .. }));
+        assert_eq!(marshaller.queue_len(), 0);
+    }
+
+    #[test]
+    fn marshaller_frames() {
+        let mut marshaller = Marshaller::new();
+        marshaller.push(U16Frame::new(b"one".to_vec()));
+        marshaller.try_push(U16Frame::new(b"two".to_vec())).unwrap();
+        let mut wire = vec![];
+        marshaller.read_to_end(&mut wire).unwrap();
+        assert_eq!(wire, b"\x00\x03one\x00\x03two");
+
+        // Deliver data byte by byte
+        let mut frames = vec![];
+        for byte in wire {
+            marshaller.write_all(&[byte]).unwrap();
+            if let Some(frame) = marshaller.pop::<U16Frame>().unwrap() {
+                frames.push(frame.into_payload());
+            }
+        }
+        assert_eq!(frames, vec![b"one".to_vec(), b"two".to_vec()]);
+        assert_eq!(marshaller.pop::<U16Frame>().unwrap(), None);
+        assert!(marshaller.drain().unwrap().is_empty());
+    }
+}
@@ -26,12 +26,18 @@ extern crate amplify;
 #[cfg(feature = "log")]
 extern crate log_crate as log;
 
+pub mod activation;
+pub mod admission;
+#[cfg(feature = "eidolon")]
+pub mod auth;
 pub mod frame;
 #[cfg(feature = "reactor")]
 pub mod tunnel;
 
 mod connection;
 mod listener;
+pub mod loopback;
+pub mod proxy;
 pub mod session;
 mod split;
 
@@ -40,12 +46,26 @@ pub mod resource;
 
 pub const READ_BUFFER_SIZE: usize = u16::MAX as usize;
 
-pub use connection::{Address, AsConnection, NetConnection, NetStream};
+pub use activation::{listen_fds, ListenFd};
+pub use admission::{
+    AdmissionFilter, Cidr, ConnectionLimit, IpAddress, IpFilter, RateLimit, Rejection,
+};
+#[cfg(feature = "eidolon")]
+pub use auth::{AllowAll, PeerAuthorizer, SharedAllowlist};
+pub use connection::{Address, AsConnection, NetConnection, NetStream, UnixAddr};
 pub use frame::{Frame, Marshaller};
-pub use listener::NetListener;
+pub use listener::{AcceptError, NetListener, ToListenerAddr};
+#[cfg(feature = "nonblocking")]
+pub use listener::{ListenerOptions, DEFAULT_BACKLOG};
+pub use loopback::{Loopback, LoopbackAddr};
 #[cfg(feature = "io-reactor")]
-pub use resource::{ListenerEvent, NetAccept, NetTransport, SessionEvent};
-pub use session::{NetProtocol, NetSession, NetStateMachine};
+pub use resource::{
+    FramedTransport, GracefulClose, ListenerEvent, NetAccept, NetTransport, SessionEvent,
+    TransportConfig, WriteBufferFull, WriteWatermarks,
+};
+pub use session::{
+    HandshakeError, HandshakeErrorKind, LayerError, NetProtocol, NetSession, NetStateMachine,
+};
 pub use split::{NetReader, NetWriter, SplitIo, SplitIoError, TcpReader, TcpWriter};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
@@ -21,23 +21,276 @@
 
 use std::io;
 use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+#[cfg(feature = "nonblocking")]
+use std::time::Duration;
+
+use crate::admission::Rejection;
+use crate::connection::{Address, NetConnection, UnixAddr};
+
+/// Default length of the queue of pending connections. This is the value used
+/// by [`std::net::TcpListener`] at the time of writing; the standard library
+/// doesn't export it, so it is hard-coded here.
+#[cfg(feature = "nonblocking")]
+pub const DEFAULT_BACKLOG: i32 = 128;
+
+/// Socket options applied to a listener before it is bound, used by
+/// [`NetListener::bind_with_options`].
+///
+/// Options which do not apply to the listener address family (like
+/// `IPV6_V6ONLY` for IPv4 or Unix sockets) are ignored. The default value
+/// matches [`NetListener::bind`]: [`DEFAULT_BACKLOG`] and no other options.
+#[cfg(feature = "nonblocking")]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ListenerOptions {
+    /// Maximum length of the queue of pending connections.
+    pub backlog: i32,
+    /// Sets `SO_REUSEADDR`. For Unix sockets, removes stale socket file left
+    /// at the path from the previous runs instead, unless some process still
+    /// listens on it.
+    pub reuse_address: bool,
+    /// Sets `SO_REUSEPORT`, allowing multiple sockets to be bound to the same
+    /// address, with the incoming connections distributed between them.
+    pub reuse_port: bool,
+    /// Sets `IPV6_V6ONLY` for IPv6 listeners; `None` keeps the system default.
+    pub only_v6: Option<bool>,
+    /// Sets `SO_RCVBUF` size, which is inherited by the accepted connections.
+    pub recv_buffer_size: Option<usize>,
+    /// Sets `SO_SNDBUF` size, which is inherited by the accepted connections.
+    pub send_buffer_size: Option<usize>,
+    /// Sets `TCP_DEFER_ACCEPT`, making the listener wake up only once the
+    /// connection has received some data or the timeout has passed. Supported
+    /// on Linux and Android only; ignored on other systems.
+    pub defer_accept: Option<Duration>,
+}
+
+#[cfg(feature = "nonblocking")]
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        ListenerOptions {
+            backlog: DEFAULT_BACKLOG,
+            reuse_address: false,
+            reuse_port: false,
+            only_v6: None,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            defer_accept: None,
+        }
+    }
+}
+
+#[cfg(feature = "nonblocking")]
+impl ListenerOptions {
+    /// Options used by [`NetListener::bind_reusable`]: `SO_REUSEADDR` and
+    /// `SO_REUSEPORT` (where supported) with the default backlog.
+    pub fn reusable() -> Self {
+        ListenerOptions {
+            reuse_address: true,
+            reuse_port: cfg!(all(unix, not(target_os = "solaris"), not(target_os = "illumos"))),
+            ..default!()
+        }
+    }
+
+    fn apply(&self, socket: &socket2::Socket) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if socket.domain()? == socket2::Domain::UNIX {
+            return Ok(());
+        }
+        socket.set_reuse_address(self.reuse_address)?;
+        #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+        if self.reuse_port {
+            socket.set_reuse_port(true)?;
+        }
+        if let Some(only_v6) = self.only_v6 {
+            if socket.domain()? == socket2::Domain::IPV6 {
+                socket.set_only_v6(only_v6)?;
+            }
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(timeout) = self.defer_accept {
+            let secs = timeout.as_secs().min(libc::c_int::MAX as u64) as libc::c_int;
+            let res = unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::IPPROTO_TCP,
+                    libc::TCP_DEFER_ACCEPT,
+                    &secs as *const libc::c_int as *const libc::c_void,
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+            if res < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates socket, applies the options, binds it to the address and
+    /// starts listening.
+    fn bind(&self, addr: &socket2::SockAddr) -> io::Result<socket2::Socket> {
+        let socket = socket2::Socket::new(addr.domain(), socket2::Type::STREAM, None)?;
+        self.apply(&socket)?;
+        socket.bind(addr)?;
+        socket.listen(self.backlog)?;
+        Ok(socket)
+    }
+}
 
-use crate::connection::NetConnection;
+/// Conversion into a local address type `A` to which a [`NetListener`] can be
+/// bound.
+pub trait ToListenerAddr<A> {
+    /// Returns the first address to bind to.
+    fn to_listener_addr(&self) -> io::Result<A> {
+        self.to_listener_addrs()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| io::ErrorKind::InvalidInput.into())
+    }
+
+    /// Returns all the addresses to try binding to, in order. Listeners are
+    /// bound to the first address which succeeds, like
+    /// [`std::net::TcpListener::bind`] does. Never returns an empty list.
+    fn to_listener_addrs(&self) -> io::Result<Vec<A>>;
+}
+
+impl<T: ToSocketAddrs + ?Sized> ToListenerAddr<SocketAddr> for T {
+    fn to_listener_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        let addrs = self.to_socket_addrs()?.collect::<Vec<_>>();
+        if addrs.is_empty() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        Ok(addrs)
+    }
+}
+
+impl<T: AsRef<Path> + ?Sized> ToListenerAddr<UnixAddr> for T {
+    fn to_listener_addrs(&self) -> io::Result<Vec<UnixAddr>> { Ok(vec![self.as_ref().into()]) }
+}
+
+/// Tries to bind to each of the addresses in turn, returning the first
+/// successfully bound listener or the last error.
+fn bind_any<A, T>(
+    addr: &impl ToListenerAddr<A>,
+    mut bind: impl FnMut(A) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut last_err = None;
+    for addr in addr.to_listener_addrs()? {
+        match bind(addr) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::ErrorKind::InvalidInput.into()))
+}
+
+/// Checks that the descriptor is a listening stream socket of one of the
+/// address `families`.
+fn check_listening(fd: &OwnedFd, families: &[libc::c_int]) -> io::Result<()> {
+    let sockopt = |opt: libc::c_int| -> io::Result<libc::c_int> {
+        let mut val: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                opt,
+                &mut val as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(val)
+    };
+    if sockopt(libc::SO_TYPE)? != libc::SOCK_STREAM || sockopt(libc::SO_ACCEPTCONN)? == 0 {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockname(
+            fd.as_raw_fd(),
+            &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if !families.contains(&(addr.ss_family as libc::c_int)) {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    Ok(())
+}
+
+/// Error accepting an incoming connection with [`NetListener::try_accept`].
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AcceptError<A: Address> {
+    /// {0}
+    #[from]
+    Io(io::Error),
+
+    /// connection from {0} was rejected by the listener: {1}
+    Rejected(A, Rejection),
+}
 
 pub trait NetListener: AsRawFd + Send {
     type Stream: NetConnection;
+    /// Local address type of the listener.
+    type Addr: Address;
 
-    fn bind(addr: &impl ToSocketAddrs) -> io::Result<Self>
+    fn bind(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
     where Self: Sized;
 
     #[cfg(feature = "nonblocking")]
-    fn bind_reusable(addr: &impl ToSocketAddrs) -> io::Result<Self>
+    fn bind_reusable(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
+    where Self: Sized;
+
+    /// Binds listener to the provided address, applying socket `options`
+    /// before binding.
+    #[cfg(feature = "nonblocking")]
+    fn bind_with_options(
+        addr: &impl ToListenerAddr<Self::Addr>,
+        options: &ListenerOptions,
+    ) -> io::Result<Self>
+    where
+        Self: Sized;
+
+    /// Adopts an already bound and listening socket, like the ones passed to
+    /// the process by the service manager with socket activation (see
+    /// [`crate::activation::listen_fds`]). Errors with
+    /// [`io::ErrorKind::InvalidInput`] if the descriptor is not a listening
+    /// stream socket of the listener address family.
+    fn from_listening_fd(fd: OwnedFd) -> io::Result<Self>
     where Self: Sized;
 
     fn accept(&self) -> io::Result<Self::Stream>;
 
-    fn local_addr(&self) -> SocketAddr;
+    /// Accepts an incoming connection like [`NetListener::accept`], but tells
+    /// apart connections which were accepted by the OS and then closed by the
+    /// listener itself, for instance since they haven't passed a protocol
+    /// check. [`crate::NetAccept`] reports such connections with
+    /// [`crate::ListenerEvent::Rejected`].
+    ///
+    /// The default implementation calls [`NetListener::accept`], which never
+    /// rejects connections.
+    fn try_accept(
+        &self,
+    ) -> Result<Self::Stream, AcceptError<<Self::Stream as NetConnection>::Addr>> {
+        self.accept().map_err(AcceptError::from)
+    }
+
+    fn local_addr(&self) -> Self::Addr;
 
     fn ttl(&self) -> io::Result<u32>;
     fn set_ttl(&self, ttl: u32) -> io::Result<()>;
@@ -51,22 +304,34 @@ pub trait NetListener: AsRawFd + Send {
 
 impl NetListener for TcpListener {
     type Stream = TcpStream;
+    type Addr = SocketAddr;
 
-    fn bind(addr: &impl ToSocketAddrs) -> io::Result<Self>
+    fn bind(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
     where Self: Sized {
-        TcpListener::bind(addr)
+        bind_any(addr, TcpListener::bind)
     }
 
     #[cfg(feature = "nonblocking")]
-    fn bind_reusable(addr: &impl ToSocketAddrs) -> io::Result<Self>
+    fn bind_reusable(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
     where Self: Sized {
-        // This is the default used by std::net::TcpListener at the time of writing. Unfortunately
-        // the standard library doesn't export this value so we have to hard-code it here.
-        const BACKLOG: i32 = 128;
+        Self::bind_with_options(addr, &ListenerOptions::reusable())
+    }
 
-        let socket = socket2::Socket::bind_reusable(addr)?;
-        socket.listen(BACKLOG)?;
-        Ok(TcpListener::from(socket))
+    #[cfg(feature = "nonblocking")]
+    fn bind_with_options(
+        addr: &impl ToListenerAddr<Self::Addr>,
+        options: &ListenerOptions,
+    ) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        socket2::Socket::bind_with_options(addr, options).map(TcpListener::from)
+    }
+
+    fn from_listening_fd(fd: OwnedFd) -> io::Result<Self>
+    where Self: Sized {
+        check_listening(&fd, &[libc::AF_INET, libc::AF_INET6])?;
+        Ok(TcpListener::from(fd))
     }
 
     fn accept(&self) -> io::Result<Self::Stream> { Ok(TcpListener::accept(self)?.0) }
@@ -94,28 +359,32 @@ impl NetListener for TcpListener {
 #[cfg(feature = "nonblocking")]
 impl NetListener for socket2::Socket {
     type Stream = socket2::Socket;
+    type Addr = SocketAddr;
 
-    fn bind(addr: &impl ToSocketAddrs) -> io::Result<Self>
+    fn bind(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
     where Self: Sized {
-        let addr = addr.to_socket_addrs()?.next().ok_or(io::ErrorKind::InvalidInput)?;
-        let socket =
-            socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM, None)?;
-        socket2::Socket::bind(&socket, &addr.into())?;
-        Ok(socket)
+        Self::bind_with_options(addr, &default!())
     }
 
-    fn bind_reusable(addr: &impl ToSocketAddrs) -> io::Result<Self>
+    fn bind_reusable(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
     where Self: Sized {
-        let addr = addr.to_socket_addrs()?.next().ok_or(io::ErrorKind::InvalidInput)?;
-        let socket =
-            socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM, None)?;
-        socket.set_reuse_address(true)?;
-        #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
-        {
-            socket.set_reuse_port(true)?;
-        }
-        socket2::Socket::bind(&socket, &addr.into())?;
-        Ok(socket)
+        Self::bind_with_options(addr, &ListenerOptions::reusable())
+    }
+
+    fn bind_with_options(
+        addr: &impl ToListenerAddr<Self::Addr>,
+        options: &ListenerOptions,
+    ) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        bind_any(addr, |addr| options.bind(&addr.into()))
+    }
+
+    fn from_listening_fd(fd: OwnedFd) -> io::Result<Self>
+    where Self: Sized {
+        check_listening(&fd, &[libc::AF_INET, libc::AF_INET6])?;
+        Ok(socket2::Socket::from(fd))
     }
 
     fn accept(&self) -> io::Result<Self::Stream> { Ok(socket2::Socket::accept(self)?.0) }
@@ -142,3 +411,148 @@ impl NetListener for socket2::Socket {
 
     fn take_error(&self) -> io::Result<Option<io::Error>> { socket2::Socket::take_error(self) }
 }
+
+impl NetListener for UnixListener {
+    type Stream = UnixStream;
+    type Addr = UnixAddr;
+
+    fn bind(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
+    where Self: Sized {
+        let addr = addr.to_listener_addr()?;
+        let path = addr.as_pathname().ok_or(io::ErrorKind::InvalidInput)?;
+        UnixListener::bind(path)
+    }
+
+    /// Binds to the provided path, removing stale socket file left at the path
+    /// from the previous runs, if any. Errors if the path exists and is not a
+    /// socket, or if the socket still accepts connections.
+    #[cfg(feature = "nonblocking")]
+    fn bind_reusable(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
+    where Self: Sized {
+        Self::bind_with_options(addr, &ListenerOptions::reusable())
+    }
+
+    /// Binds to the provided path. If `options.reuse_address` is set, removes
+    /// stale socket file left at the path, like [`NetListener::bind_reusable`].
+    /// TCP- and IP-specific options are ignored.
+    #[cfg(feature = "nonblocking")]
+    fn bind_with_options(
+        addr: &impl ToListenerAddr<Self::Addr>,
+        options: &ListenerOptions,
+    ) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        let addr = addr.to_listener_addr()?;
+        let path = addr.as_pathname().ok_or(io::ErrorKind::InvalidInput)?;
+        if options.reuse_address {
+            match path.symlink_metadata() {
+                Ok(meta) if meta.file_type().is_socket() => match UnixStream::connect(path) {
+                    // The socket is still served by some other process
+                    Ok(_) => return Err(io::ErrorKind::AddrInUse.into()),
+                    Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                        std::fs::remove_file(path)?
+                    }
+                    Err(err) => return Err(err),
+                },
+                Ok(_) => return Err(io::ErrorKind::AlreadyExists.into()),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        options.bind(&socket2::SockAddr::unix(path)?).map(UnixListener::from)
+    }
+
+    fn from_listening_fd(fd: OwnedFd) -> io::Result<Self>
+    where Self: Sized {
+        check_listening(&fd, &[libc::AF_UNIX])?;
+        Ok(UnixListener::from(fd))
+    }
+
+    fn accept(&self) -> io::Result<Self::Stream> { Ok(UnixListener::accept(self)?.0) }
+
+    fn local_addr(&self) -> Self::Addr {
+        UnixListener::local_addr(self).expect("Unix listener doesn't have local address").into()
+    }
+
+    /// Unix domain sockets have no IP TTL; always errors with
+    /// [`io::ErrorKind::Unsupported`].
+    fn ttl(&self) -> io::Result<u32> { Err(io::ErrorKind::Unsupported.into()) }
+
+    /// Unix domain sockets have no IP TTL; always errors with
+    /// [`io::ErrorKind::Unsupported`].
+    fn set_ttl(&self, _ttl: u32) -> io::Result<()> { Err(io::ErrorKind::Unsupported.into()) }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixListener::set_nonblocking(self, nonblocking)
+    }
+
+    fn try_clone(&self) -> io::Result<Self>
+    where Self: Sized {
+        UnixListener::try_clone(self)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> { UnixListener::take_error(self) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "nonblocking")]
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("netservices-{}-{name}.sock", std::process::id()))
+    }
+
+    #[test]
+    fn socket_addrs_all() {
+        let addrs: Vec<SocketAddr> = "localhost:0".to_listener_addrs().unwrap();
+        assert!(!addrs.is_empty());
+        let none: &[SocketAddr] = &[];
+        assert!(none.to_listener_addrs().is_err());
+    }
+
+    #[test]
+    fn bind_any_fallback() {
+        let addrs: &[SocketAddr] =
+            &[SocketAddr::from(([127, 0, 0, 1], 1)), SocketAddr::from(([127, 0, 0, 1], 2))];
+        let mut tried = vec![];
+        let res = bind_any(&addrs, |addr| {
+            tried.push(addr);
+            if addr.port() == 1 {
+                Err(io::ErrorKind::AddrInUse.into())
+            } else {
+                Ok(addr)
+            }
+        });
+        assert_eq!(res.unwrap(), addrs[1]);
+        assert_eq!(tried, addrs);
+    }
+
+    #[test]
+    #[cfg(feature = "nonblocking")]
+    fn unix_reuse_stale() {
+        let path = socket_path("stale");
+        let _ = std::fs::remove_file(&path);
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let listener = <UnixListener as NetListener>::bind_reusable(&path).unwrap();
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "nonblocking")]
+    fn unix_reuse_live() {
+        let path = socket_path("live");
+        let _ = std::fs::remove_file(&path);
+        let live = UnixListener::bind(&path).unwrap();
+        let err = <UnixListener as NetListener>::bind_reusable(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(path.exists());
+        drop(live);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,444 @@
+// Library for building scalable privacy-preserving microservices P2P nodes
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2022-2023 by
+//     Dr. Maxim Orlovsky <orlovsky@cyphernet.org>
+//
+// Copyright 2022-2023 Cyphernet DAO, Switzerland
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory loopback connections, which can be used to run sessions,
+//! handshakes and reactor resources without binding network ports - for
+//! instance, in tests.
+//!
+//! The connections are created in pairs using [`Loopback::pair`]; each of them
+//! is backed by a Unix `socketpair`, so it has a file descriptor and can be
+//! polled by the reactor like any other network connection.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+
+use cyphernet::addr::{Addr, Host};
+
+use crate::split::{join_cloned, split_cloned, TcpReader, TcpWriter};
+use crate::{NetConnection, NetSession, NetStream, SplitIo, SplitIoError};
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Address of a [`Loopback`] connection end, unique within the process (until
+/// `u16` identifiers wrap around).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct LoopbackAddr(u16);
+
+impl LoopbackAddr {
+    fn next() -> Self { LoopbackAddr(NEXT_ID.fetch_add(1, Ordering::Relaxed)) }
+
+    pub fn id(self) -> u16 { self.0 }
+}
+
+impl Display for LoopbackAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { write!(f, "loopback:{}", self.0) }
+}
+
+impl Host for LoopbackAddr {
+    fn requires_proxy(&self) -> bool { false }
+}
+
+impl Addr for LoopbackAddr {
+    fn port(&self) -> u16 { self.0 }
+}
+
+/// One end of an in-memory duplex connection.
+#[derive(Debug)]
+pub struct Loopback {
+    stream: UnixStream,
+    local: LoopbackAddr,
+    remote: LoopbackAddr,
+}
+
+impl Loopback {
+    /// Creates a pair of connected loopback connections.
+    pub fn pair() -> io::Result<(Loopback, Loopback)> {
+        let (a, b) = UnixStream::pair()?;
+        let (addr_a, addr_b) = (LoopbackAddr::next(), LoopbackAddr::next());
+        Ok((
+            Loopback {
+                stream: a,
+                local: addr_a,
+                remote: addr_b,
+            },
+            Loopback {
+                stream: b,
+                local: addr_b,
+                remote: addr_a,
+            },
+        ))
+    }
+}
+
+impl AsRawFd for Loopback {
+    fn as_raw_fd(&self) -> RawFd { self.stream.as_raw_fd() }
+}
+
+impl Read for Loopback {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.stream.read(buf) }
+}
+
+impl Write for Loopback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.stream.write(buf) }
+    fn flush(&mut self) -> io::Result<()> { self.stream.flush() }
+}
+
+impl NetStream for Loopback {}
+impl NetConnection for Loopback {
+    type Addr = LoopbackAddr;
+
+    /// Loopback connections can't be connected to an address; use
+    /// [`Loopback::pair`] instead. Always errors with
+    /// [`io::ErrorKind::Unsupported`].
+    fn connect_blocking(_addr: Self::Addr, _timeout: Duration) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Loopback connections can't be connected to an address; use
+    /// [`Loopback::pair`] instead. Always errors with
+    /// [`io::ErrorKind::Unsupported`].
+    #[cfg(feature = "nonblocking")]
+    fn connect_nonblocking(_addr: Self::Addr, _timeout: Duration) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Loopback connections can't be connected to an address; use
+    /// [`Loopback::pair`] instead. Always errors with
+    /// [`io::ErrorKind::Unsupported`].
+    #[cfg(feature = "nonblocking")]
+    fn connect_reusable_nonblocking(
+        _local_addr: Self::Addr,
+        _remote_addr: Self::Addr,
+    ) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> { self.stream.shutdown(how) }
+
+    fn remote_addr(&self) -> io::Result<Self::Addr> { Ok(self.remote) }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> { Ok(self.local) }
+
+    #[cfg(feature = "nonblocking")]
+    fn set_tcp_keepalive(&mut self, keepalive: &socket2::TcpKeepalive) -> io::Result<()> {
+        NetConnection::set_tcp_keepalive(&mut self.stream, keepalive)
+    }
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(dur)
+    }
+    fn set_write_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(dur)
+    }
+    fn read_timeout(&self) -> io::Result<Option<Duration>> { self.stream.read_timeout() }
+    fn write_timeout(&self) -> io::Result<Option<Duration>> { self.stream.write_timeout() }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> { NetConnection::peek(&self.stream, buf) }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> io::Result<()> {
+        NetConnection::set_nodelay(&mut self.stream, nodelay)
+    }
+    fn nodelay(&self) -> io::Result<bool> { NetConnection::nodelay(&self.stream) }
+    fn set_ttl(&mut self, ttl: u32) -> io::Result<()> {
+        NetConnection::set_ttl(&mut self.stream, ttl)
+    }
+    fn ttl(&self) -> io::Result<u32> { NetConnection::ttl(&self.stream) }
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Loopback {
+            stream: self.stream.try_clone()?,
+            local: self.local,
+            remote: self.remote,
+        })
+    }
+    fn take_error(&self) -> io::Result<Option<io::Error>> { self.stream.take_error() }
+}
+
+impl NetSession for Loopback {
+    type Inner = Self;
+    type Connection = Self;
+    type Artifact = LoopbackAddr;
+
+    fn run_handshake(&mut self) -> io::Result<()> { Ok(()) }
+
+    fn artifact(&self) -> Option<Self::Artifact> { Some(self.remote) }
+
+    fn as_connection(&self) -> &Self::Connection { self }
+
+    fn as_connection_mut(&mut self) -> &mut Self::Connection { self }
+
+    fn disconnect(self) -> io::Result<()> { self.stream.shutdown(Shutdown::Both) }
+}
+
+impl SplitIo for Loopback {
+    type Read = TcpReader<Self>;
+    type Write = TcpWriter<Self>;
+
+    fn split_io(self) -> Result<(Self::Read, Self::Write), SplitIoError<Self>> {
+        split_cloned(self)
+    }
+
+    fn from_split_io(read: Self::Read, write: Self::Write) -> Self { join_cloned(read, write) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use cyphernet::encrypt::noise::{HandshakePattern, Keyset, NoiseState};
+    use cyphernet::{x25519, Sha256};
+
+    use super::*;
+    use crate::session::{NoiseSession, ZeroInit};
+    use crate::{HandshakeError, HandshakeErrorKind, LayerError, NetProtocol, NetStateMachine};
+
+    type Noise = NoiseSession<x25519::PrivateKey, Sha256, Loopback>;
+
+    fn noise(connection: Loopback, initiator: bool) -> Noise {
+        let state = NoiseState::initialize::<32>(
+            HandshakePattern::nn(),
+            initiator,
+            &[],
+            Keyset::noise_nn(),
+        );
+        NoiseSession::with(connection, state)
+    }
+
+    #[test]
+    fn pair() {
+        let (mut a, mut b) = Loopback::pair().unwrap();
+        assert_eq!(a.local_addr().unwrap(), b.remote_addr().unwrap());
+        assert_eq!(a.remote_addr().unwrap(), b.local_addr().unwrap());
+        assert_ne!(a.local_addr().unwrap(), b.local_addr().unwrap());
+
+        a.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn split_join() {
+        let (a, mut b) = Loopback::pair().unwrap();
+        let addr = a.local_addr().unwrap();
+        let (mut reader, mut writer) = a.split_io().unwrap();
+        writer.write_all(b"ping").unwrap();
+        b.write_all(b"pong").unwrap();
+
+        let mut buf = [0u8; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        let a = Loopback::from_split_io(reader, writer);
+        assert_eq!(a.local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    #[should_panic]
+    fn split_join_mismatch() {
+        let (a, b) = Loopback::pair().unwrap();
+        let (reader, _) = a.split_io().unwrap();
+        let (_, writer) = b.split_io().unwrap();
+        Loopback::from_split_io(reader, writer);
+    }
+
+    /// Error which is neither `Send` nor `Sync`.
+    #[derive(Debug, Display, Error)]
+    #[display("{0}")]
+    struct LocalError(std::rc::Rc<str>);
+
+    /// Responder expecting a single `ping` act and replying with `pong`.
+    #[derive(Default)]
+    struct Ping(bool);
+
+    impl NetStateMachine for Ping {
+        const NAME: &'static str = "ping";
+        type Init = ZeroInit;
+        type Artifact = ();
+        type Error = LocalError;
+
+        fn init(&mut self, _: Self::Init) {}
+
+        fn next_read_len(&self) -> usize {
+            if self.0 {
+                0
+            } else {
+                4
+            }
+        }
+
+        fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Self::Error> {
+            if input != b"ping" {
+                return Err(LocalError("unexpected act".into()));
+            }
+            self.0 = true;
+            Ok(b"pong".to_vec())
+        }
+
+        fn artifact(&self) -> Option<Self::Artifact> { self.0.then_some(()) }
+
+        fn is_init(&self) -> bool { true }
+    }
+
+    #[test]
+    fn handshake_responder() {
+        // The responder must receive the remote act before advancing
+        let (a, mut b) = Loopback::pair().unwrap();
+        b.write_all(b"ping").unwrap();
+        let mut session = NetProtocol::<Ping, _>::new(a);
+        session.run_handshake().unwrap();
+        assert!(session.is_established());
+        let mut buf = [0u8; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[test]
+    fn handshake_error() {
+        let (a, mut b) = Loopback::pair().unwrap();
+        b.write_all(b"pong").unwrap();
+        let err = NetProtocol::<Ping, _>::new(a).run_handshake().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        let err = HandshakeError::from_io(&err).unwrap();
+        assert_eq!(err.layer, Ping::NAME);
+        assert_eq!(err.kind, HandshakeErrorKind::ProtocolViolation);
+        assert_eq!(err.source_as::<LayerError>().unwrap().0, "unexpected act");
+    }
+
+    #[test]
+    fn noise_handshake() {
+        let (a, b) = Loopback::pair().unwrap();
+        let (addr_a, addr_b) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        let responder = thread::spawn(move || {
+            let mut session = noise(b, false);
+            session.run_handshake().unwrap();
+            let mut buf = [0u8; 4];
+            session.read_exact(&mut buf).unwrap();
+            session.write_all(&buf).unwrap();
+            session.artifact().unwrap()
+        });
+
+        let mut session = noise(a, true);
+        session.run_handshake().unwrap();
+        session.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        session.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        let initiator = session.artifact().unwrap();
+        let responder = responder.join().unwrap();
+        assert_eq!(initiator.state.handshake_hash, responder.state.handshake_hash);
+        assert_eq!(initiator.session, addr_b);
+        assert_eq!(responder.session, addr_a);
+    }
+
+    #[test]
+    #[cfg(feature = "eidolon")]
+    fn eidolon_handshake() {
+        use cyphernet::{ed25519, EcSign, EcSk};
+
+        use crate::session::{EidolonRuntime, EidolonSession};
+        use crate::AllowAll;
+
+        let (sk_a, pk_a) = ed25519::PrivateKey::generate_keypair();
+        let (sk_b, pk_b) = ed25519::PrivateKey::generate_keypair();
+        let (cert_a, cert_b) = (sk_a.cert().unwrap(), sk_b.cert().unwrap());
+
+        let (a, b) = Loopback::pair().unwrap();
+        let responder = thread::spawn(move || {
+            let runtime = EidolonRuntime::responder_with_authorizer(sk_b, cert_b, vec![pk_a]);
+            let mut session = EidolonSession::with(noise(b, false), runtime);
+            session.run_handshake().unwrap();
+            session.artifact().unwrap().state.pk
+        });
+
+        let runtime = EidolonRuntime::initiator_with_authorizer(sk_a, cert_a, AllowAll);
+        let mut session = EidolonSession::with(noise(a, true), runtime);
+        session.run_handshake().unwrap();
+        assert_eq!(session.artifact().unwrap().state.pk, pk_b);
+        assert_eq!(responder.join().unwrap(), pk_a);
+    }
+
+    #[test]
+    #[cfg(feature = "eidolon")]
+    fn eidolon_unauthorized() {
+        use cyphernet::{ed25519, EcSign, EcSk};
+
+        use crate::session::{EidolonRuntime, EidolonSession};
+        use crate::AllowAll;
+
+        let (sk_a, _) = ed25519::PrivateKey::generate_keypair();
+        let (sk_b, _) = ed25519::PrivateKey::generate_keypair();
+        let (_, pk_other) = ed25519::PrivateKey::generate_keypair();
+        let (cert_a, cert_b) = (sk_a.cert().unwrap(), sk_b.cert().unwrap());
+
+        let (a, b) = Loopback::pair().unwrap();
+        let responder = thread::spawn(move || {
+            let runtime = EidolonRuntime::responder_with_authorizer(sk_b, cert_b, vec![pk_other]);
+            let mut session = EidolonSession::with(noise(b, false), runtime);
+            session.run_handshake().unwrap_err()
+        });
+
+        let runtime = EidolonRuntime::initiator_with_authorizer(sk_a, cert_a, AllowAll);
+        let mut session = EidolonSession::with(noise(a, true), runtime);
+        assert!(session.run_handshake().is_err());
+
+        let err = responder.join().unwrap();
+        let err = HandshakeError::from_io(&err).unwrap();
+        assert_eq!(err.kind, HandshakeErrorKind::AuthRejected);
+    }
+
+    #[test]
+    #[cfg(feature = "eidolon")]
+    fn eidolon_empty_allowlist() {
+        use cyphernet::{ed25519, EcSign, EcSk};
+
+        use crate::session::{EidolonRuntime, EidolonSession};
+
+        let (sk_a, pk_a) = ed25519::PrivateKey::generate_keypair();
+        let (sk_b, pk_b) = ed25519::PrivateKey::generate_keypair();
+        let (cert_a, cert_b) = (sk_a.cert().unwrap(), sk_b.cert().unwrap());
+
+        // Constructors taking a list of ids allow any remote id if the list is empty
+        let (a, b) = Loopback::pair().unwrap();
+        let responder = thread::spawn(move || {
+            let runtime = EidolonRuntime::responder(sk_b, cert_b, vec![]);
+            let mut session = EidolonSession::with(noise(b, false), runtime);
+            session.run_handshake().unwrap();
+            session.artifact().unwrap().state.pk
+        });
+
+        let runtime = EidolonRuntime::initiator(sk_a, cert_a, vec![]);
+        let mut session = EidolonSession::with(noise(a, true), runtime);
+        session.run_handshake().unwrap();
+        assert_eq!(session.artifact().unwrap().state.pk, pk_b);
+        assert_eq!(responder.join().unwrap(), pk_a);
+    }
+}
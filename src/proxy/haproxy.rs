@@ -0,0 +1,880 @@
+// Library for building scalable privacy-preserving microservices P2P nodes
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2022-2023 by
+//     Dr. Maxim Orlovsky <orlovsky@cyphernet.org>
+//
+// Copyright 2022-2023 Cyphernet DAO, Switzerland
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Server side of the [PROXY protocol] (versions 1 and 2), which is used by
+//! load balancers (like HAProxy) to pass the original client address to the
+//! server.
+//!
+//! The header can be read in two ways:
+//! - without blocking, by the [`ProxyHeaderParser`] session layer (see [`ProxyHeaderSession`]),
+//!   which provides the header as a part of the session artifact once the transport is established;
+//! - synchronously, by [`ProxyListener`], which reads the header before the accepted connection is
+//!   returned to the caller. The returned [`Proxied`] connection reports the original client
+//!   address as its remote address and session artifact, so it is visible in
+//!   [`crate::ListenerEvent::Accepted`] when the listener is used with [`crate::NetAccept`]. The
+//!   read blocks the thread calling `accept`, so the listener must not be used with the reactor.
+//!   Connections with a missing or invalid header are reported with
+//!   [`crate::ListenerEvent::Rejected`].
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::admission::Rejection;
+#[cfg(feature = "nonblocking")]
+use crate::listener::ListenerOptions;
+use crate::listener::{AcceptError, ToListenerAddr};
+use crate::session::ZeroInit;
+use crate::split::{join_cloned, split_cloned};
+use crate::{
+    NetConnection, NetListener, NetProtocol, NetSession, NetStateMachine, NetStream, SplitIo,
+    SplitIoError, TcpReader, TcpWriter,
+};
+
+/// Session reading the PROXY header from the accepted connection without
+/// blocking.
+pub type ProxyHeaderSession<S> = NetProtocol<ProxyHeaderParser, S>;
+
+/// Default maximum time to wait for the PROXY header of an accepted
+/// connection.
+pub const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(1);
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+
+/// Errors parsing PROXY protocol header.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ProxyHeaderError {
+    /// connection doesn't start with PROXY protocol header
+    NoHeader,
+
+    /// PROXY protocol v1 header exceeds maximum length
+    TooLong,
+
+    /// invalid PROXY protocol v1 header
+    InvalidV1,
+
+    /// not supported PROXY protocol version {0}
+    VersionNotSupported(u8),
+
+    /// invalid PROXY protocol v2 command {0:#x}
+    InvalidCommand(u8),
+
+    /// PROXY protocol v2 address block is too short for its family
+    InvalidAddrLen,
+
+    /// PROXY header is already read
+    Completed,
+}
+
+impl From<ProxyHeaderError> for io::Error {
+    fn from(err: ProxyHeaderError) -> Self { io::Error::new(io::ErrorKind::InvalidData, err) }
+}
+
+/// Information from the PROXY protocol header.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ProxyHeader {
+    /// Connection made by the proxy on its own behalf (for instance, a health
+    /// check); the connection addresses are the real ones.
+    Local,
+
+    /// Connection from an unknown or not supported address family; the
+    /// connection addresses are the real ones.
+    Unknown,
+
+    /// Connection proxied on behalf of a client.
+    Proxied {
+        /// Original client address.
+        source: SocketAddr,
+        /// Original destination address, i.e. the address of the proxy
+        /// accepted the client connection.
+        destination: SocketAddr,
+    },
+}
+
+impl ProxyHeader {
+    /// Returns the original client address, if known.
+    pub fn source(&self) -> Option<SocketAddr> {
+        match self {
+            ProxyHeader::Proxied { source, .. } => Some(*source),
+            ProxyHeader::Local | ProxyHeader::Unknown => None,
+        }
+    }
+
+    /// Reads PROXY protocol header of version 1 or 2 from the stream, leaving
+    /// the data following the header in the stream.
+    ///
+    /// The stream must be in a blocking mode; the read blocks until the whole
+    /// header is received or the stream read timeout is reached.
+    pub fn read(stream: &mut impl Read) -> io::Result<ProxyHeader> {
+        let mut parser = ProxyHeaderParser::new();
+        loop {
+            let mut input = vec![0u8; parser.next_read_len()];
+            stream.read_exact(&mut input)?;
+            parser.advance(&input)?;
+            if let Some(header) = parser.header() {
+                return Ok(header);
+            }
+        }
+    }
+
+    /// Parses PROXY protocol v1 header line, excluding the final CRLF.
+    pub fn parse_v1(line: &[u8]) -> Result<ProxyHeader, ProxyHeaderError> {
+        let line = std::str::from_utf8(line).map_err(|_| ProxyHeaderError::InvalidV1)?;
+        let mut parts = line.split(' ');
+        if parts.next() != Some("PROXY") {
+            return Err(ProxyHeaderError::InvalidV1);
+        }
+        let parse_ip = |part: Option<&str>, v6: bool| -> Result<_, ProxyHeaderError> {
+            let part = part.ok_or(ProxyHeaderError::InvalidV1)?;
+            match v6 {
+                false => Ipv4Addr::from_str(part).map(Into::into),
+                true => Ipv6Addr::from_str(part).map(Into::into),
+            }
+            .map_err(|_| ProxyHeaderError::InvalidV1)
+        };
+        let parse_port = |part: Option<&str>| -> Result<u16, ProxyHeaderError> {
+            part.ok_or(ProxyHeaderError::InvalidV1)?
+                .parse()
+                .map_err(|_| ProxyHeaderError::InvalidV1)
+        };
+        let v6 = match parts.next() {
+            Some("TCP4") => false,
+            Some("TCP6") => true,
+            Some("UNKNOWN") => return Ok(ProxyHeader::Unknown),
+            _ => return Err(ProxyHeaderError::InvalidV1),
+        };
+        let source = parse_ip(parts.next(), v6)?;
+        let destination = parse_ip(parts.next(), v6)?;
+        let source_port = parse_port(parts.next())?;
+        let destination_port = parse_port(parts.next())?;
+        if parts.next().is_some() {
+            return Err(ProxyHeaderError::InvalidV1);
+        }
+        Ok(ProxyHeader::Proxied {
+            source: SocketAddr::new(source, source_port),
+            destination: SocketAddr::new(destination, destination_port),
+        })
+    }
+
+    /// Parses PROXY protocol v2 header from its version and command byte,
+    /// address family byte and the address block (which may include TLVs,
+    /// which are ignored).
+    pub fn parse_v2(
+        version_command: u8,
+        family: u8,
+        addrs: &[u8],
+    ) -> Result<ProxyHeader, ProxyHeaderError> {
+        if version_command >> 4 != 2 {
+            return Err(ProxyHeaderError::VersionNotSupported(version_command >> 4));
+        }
+        match version_command & 0x0F {
+            0x00 => return Ok(ProxyHeader::Local),
+            0x01 => {}
+            _ => return Err(ProxyHeaderError::InvalidCommand(version_command)),
+        }
+        let port = |pos: usize| u16::from_be_bytes([addrs[pos], addrs[pos + 1]]);
+        match family {
+            // TCP and UDP over IPv4
+            0x11 | 0x12 => {
+                if addrs.len() < 12 {
+                    return Err(ProxyHeaderError::InvalidAddrLen);
+                }
+                let source = <[u8; 4]>::try_from(&addrs[0..4]).expect("fixed length");
+                let destination = <[u8; 4]>::try_from(&addrs[4..8]).expect("fixed length");
+                Ok(ProxyHeader::Proxied {
+                    source: SocketAddr::new(Ipv4Addr::from(source).into(), port(8)),
+                    destination: SocketAddr::new(Ipv4Addr::from(destination).into(), port(10)),
+                })
+            }
+            // TCP and UDP over IPv6
+            0x21 | 0x22 => {
+                if addrs.len() < 36 {
+                    return Err(ProxyHeaderError::InvalidAddrLen);
+                }
+                let source = <[u8; 16]>::try_from(&addrs[0..16]).expect("fixed length");
+                let destination = <[u8; 16]>::try_from(&addrs[16..32]).expect("fixed length");
+                Ok(ProxyHeader::Proxied {
+                    source: SocketAddr::new(Ipv6Addr::from(source).into(), port(32)),
+                    destination: SocketAddr::new(Ipv6Addr::from(destination).into(), port(34)),
+                })
+            }
+            _ => Ok(ProxyHeader::Unknown),
+        }
+    }
+}
+
+/// Responder state machine reading PROXY protocol header of version 1 or 2,
+/// which doesn't send anything to the peer and doesn't consume any data
+/// following the header.
+///
+/// Unlike [`ProxyListener`], the state machine doesn't block: the header is
+/// read as the data arrive, with the handshake timeout of the
+/// [`crate::NetTransport`] applying to it.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub enum ProxyHeaderParser {
+    /// Awaiting PROXY protocol v2 signature or the beginning of v1 header.
+    #[default]
+    Signature,
+
+    /// Awaiting PROXY protocol v2 version and command byte, address family
+    /// byte and the length of the address block.
+    V2Header,
+
+    /// Awaiting PROXY protocol v2 address block of the given length.
+    V2Addrs {
+        /// Version and command byte.
+        version_command: u8,
+        /// Address family byte.
+        family: u8,
+        /// Length of the address block.
+        len: usize,
+    },
+
+    /// Reading PROXY protocol v1 header line byte by byte.
+    V1Line(Vec<u8>),
+
+    /// The header is read.
+    Complete(ProxyHeader),
+
+    /// The header is invalid.
+    Failed(ProxyHeaderError),
+}
+
+impl ProxyHeaderParser {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, ProxyHeaderError> {
+        debug_assert_eq!(input.len(), self.next_read_len());
+        match self {
+            ProxyHeaderParser::Signature => {
+                if input == V2_SIGNATURE {
+                    *self = ProxyHeaderParser::V2Header;
+                } else if input.starts_with(V1_PREFIX) {
+                    *self = ProxyHeaderParser::V1Line(input.to_vec());
+                } else {
+                    return self.fail(ProxyHeaderError::NoHeader);
+                }
+            }
+            ProxyHeaderParser::V2Header => {
+                let len = u16::from_be_bytes([input[2], input[3]]) as usize;
+                *self = ProxyHeaderParser::V2Addrs {
+                    version_command: input[0],
+                    family: input[1],
+                    len,
+                };
+                if len == 0 {
+                    return self.advance(&[]);
+                }
+            }
+            ProxyHeaderParser::V2Addrs {
+                version_command,
+                family,
+                ..
+            } => {
+                let header = ProxyHeader::parse_v2(*version_command, *family, input);
+                return self.complete(header);
+            }
+            ProxyHeaderParser::V1Line(line) => {
+                line.extend(input);
+                if line.ends_with(b"\r\n") {
+                    let header = ProxyHeader::parse_v1(&line[..line.len() - 2]);
+                    return self.complete(header);
+                }
+                if line.len() >= V1_MAX_LEN {
+                    return self.fail(ProxyHeaderError::TooLong);
+                }
+            }
+            ProxyHeaderParser::Complete(_) => return Err(ProxyHeaderError::Completed),
+            ProxyHeaderParser::Failed(err) => return Err(err.clone()),
+        }
+        Ok(vec![])
+    }
+
+    pub fn next_read_len(&self) -> usize {
+        match self {
+            ProxyHeaderParser::Signature => V2_SIGNATURE.len(),
+            ProxyHeaderParser::V2Header => 4,
+            ProxyHeaderParser::V2Addrs { len, .. } => *len,
+            ProxyHeaderParser::V1Line(_) => 1,
+            ProxyHeaderParser::Complete(_) | ProxyHeaderParser::Failed(_) => 0,
+        }
+    }
+
+    /// Returns the header, if it is already read.
+    pub fn header(&self) -> Option<ProxyHeader> {
+        match self {
+            ProxyHeaderParser::Complete(header) => Some(*header),
+            _ => None,
+        }
+    }
+
+    fn complete(
+        &mut self,
+        header: Result<ProxyHeader, ProxyHeaderError>,
+    ) -> Result<Vec<u8>, ProxyHeaderError> {
+        match header {
+            Ok(header) => {
+                *self = ProxyHeaderParser::Complete(header);
+                Ok(vec![])
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+
+    fn fail(&mut self, err: ProxyHeaderError) -> Result<Vec<u8>, ProxyHeaderError> {
+        *self = ProxyHeaderParser::Failed(err.clone());
+        Err(err)
+    }
+}
+
+impl NetStateMachine for ProxyHeaderParser {
+    const NAME: &'static str = "proxy-header";
+    type Init = ZeroInit;
+    type Artifact = ProxyHeader;
+    type Error = ProxyHeaderError;
+
+    fn init(&mut self, _: Self::Init) {}
+
+    fn next_read_len(&self) -> usize { self.next_read_len() }
+
+    fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Self::Error> { self.advance(input) }
+
+    fn artifact(&self) -> Option<Self::Artifact> { self.header() }
+
+    fn is_init(&self) -> bool { true }
+}
+
+/// Connection accepted from a PROXY protocol-speaking load balancer, which
+/// reports the original client address from the [`ProxyHeader`] as its remote
+/// address and session artifact.
+#[derive(Debug)]
+pub struct Proxied<C: NetConnection> {
+    connection: C,
+    header: ProxyHeader,
+}
+
+impl<C: NetConnection> Proxied<C> {
+    /// Reads PROXY header from the connection, waiting for it for no longer
+    /// than `timeout` (otherwise failing with [`io::ErrorKind::TimedOut`]
+    /// error). The connection is left in a blocking mode without read timeout.
+    pub fn accept(mut connection: C, timeout: Duration) -> io::Result<Self> {
+        connection.set_nonblocking(false)?;
+        connection.set_read_timeout(Some(timeout))?;
+        let header = ProxyHeader::read(&mut connection).map_err(|err| match err.kind() {
+            io::ErrorKind::WouldBlock => io::Error::from(io::ErrorKind::TimedOut),
+            _ => err,
+        })?;
+        connection.set_read_timeout(None)?;
+        Ok(Self { connection, header })
+    }
+
+    pub fn header(&self) -> ProxyHeader { self.header }
+
+    pub fn as_inner(&self) -> &C { &self.connection }
+
+    pub fn into_inner(self) -> C { self.connection }
+}
+
+impl<C: NetConnection> AsRawFd for Proxied<C> {
+    fn as_raw_fd(&self) -> RawFd { self.connection.as_raw_fd() }
+}
+
+impl<C: NetConnection> Read for Proxied<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.connection.read(buf) }
+}
+
+impl<C: NetConnection> Write for Proxied<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.connection.write(buf) }
+
+    fn flush(&mut self) -> io::Result<()> { self.connection.flush() }
+}
+
+impl<C: NetConnection> NetStream for Proxied<C> {}
+
+/// Outgoing connections are not proxied, hence they have
+/// [`ProxyHeader::Unknown`] header.
+impl<C: NetConnection> NetConnection for Proxied<C>
+where C::Addr: From<SocketAddr>
+{
+    type Addr = C::Addr;
+
+    fn connect_blocking(addr: Self::Addr, timeout: Duration) -> io::Result<Self> {
+        C::connect_blocking(addr, timeout).map(|connection| Proxied {
+            connection,
+            header: ProxyHeader::Unknown,
+        })
+    }
+
+    #[cfg(feature = "nonblocking")]
+    fn connect_nonblocking(addr: Self::Addr, timeout: Duration) -> io::Result<Self> {
+        C::connect_nonblocking(addr, timeout).map(|connection| Proxied {
+            connection,
+            header: ProxyHeader::Unknown,
+        })
+    }
+
+    #[cfg(feature = "nonblocking")]
+    fn connect_reusable_nonblocking(
+        local_addr: Self::Addr,
+        remote_addr: Self::Addr,
+    ) -> io::Result<Self> {
+        C::connect_reusable_nonblocking(local_addr, remote_addr).map(|connection| Proxied {
+            connection,
+            header: ProxyHeader::Unknown,
+        })
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> { self.connection.shutdown(how) }
+
+    fn remote_addr(&self) -> io::Result<Self::Addr> {
+        match self.header.source() {
+            Some(source) => Ok(source.into()),
+            None => self.connection.remote_addr(),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> { self.connection.local_addr() }
+
+    #[cfg(feature = "nonblocking")]
+    fn set_tcp_keepalive(&mut self, keepalive: &socket2::TcpKeepalive) -> io::Result<()> {
+        self.connection.set_tcp_keepalive(keepalive)
+    }
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.connection.set_read_timeout(dur)
+    }
+    fn set_write_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.connection.set_write_timeout(dur)
+    }
+    fn read_timeout(&self) -> io::Result<Option<Duration>> { self.connection.read_timeout() }
+    fn write_timeout(&self) -> io::Result<Option<Duration>> { self.connection.write_timeout() }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> { self.connection.peek(buf) }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> io::Result<()> {
+        self.connection.set_nodelay(nodelay)
+    }
+    fn nodelay(&self) -> io::Result<bool> { self.connection.nodelay() }
+    fn set_ttl(&mut self, ttl: u32) -> io::Result<()> { self.connection.set_ttl(ttl) }
+    fn ttl(&self) -> io::Result<u32> { self.connection.ttl() }
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.connection.set_nonblocking(nonblocking)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Proxied {
+            connection: self.connection.try_clone()?,
+            header: self.header,
+        })
+    }
+    fn take_error(&self) -> io::Result<Option<io::Error>> { self.connection.take_error() }
+}
+
+impl<C: NetConnection> SplitIo for Proxied<C>
+where C::Addr: From<SocketAddr>
+{
+    type Read = TcpReader<Self>;
+    type Write = TcpWriter<Self>;
+
+    fn split_io(self) -> Result<(Self::Read, Self::Write), SplitIoError<Self>> {
+        split_cloned(self)
+    }
+
+    fn from_split_io(read: Self::Read, write: Self::Write) -> Self { join_cloned(read, write) }
+}
+
+impl<C> NetSession for Proxied<C>
+where
+    C: NetConnection + NetSession<Artifact = SocketAddr>,
+    <C as NetConnection>::Addr: From<SocketAddr>,
+{
+    type Inner = Self;
+    type Connection = Self;
+    type Artifact = SocketAddr;
+
+    fn run_handshake(&mut self) -> io::Result<()> { Ok(()) }
+
+    fn artifact(&self) -> Option<Self::Artifact> {
+        self.header.source().or_else(|| self.connection.artifact())
+    }
+
+    fn as_connection(&self) -> &Self::Connection { self }
+
+    fn as_connection_mut(&mut self) -> &mut Self::Connection { self }
+
+    fn disconnect(mut self) -> io::Result<()> { self.connection.shutdown(Shutdown::Both) }
+}
+
+/// Listener accepting connections from a PROXY protocol-speaking load
+/// balancer. Each accepted connection must start with the PROXY header.
+///
+/// NB: The header is read synchronously during the `accept` call, which blocks
+/// the calling thread for up to the header timeout ([`PROXY_HEADER_TIMEOUT`]
+/// by default). The listener must not be used with the reactor: when it is
+/// wrapped into [`crate::NetAccept`], no other resources are served while the
+/// header is awaited, and a client connecting without sending the header
+/// stalls the reactor for the whole timeout. With the reactor, read the header
+/// without blocking with [`ProxyHeaderSession`] instead.
+///
+/// Connections with a missing or invalid header are closed, and
+/// [`NetListener::try_accept`] reports them with [`AcceptError::Rejected`].
+#[derive(Debug)]
+pub struct ProxyListener<L: NetListener> {
+    listener: L,
+    timeout: Duration,
+}
+
+impl<L: NetListener> ProxyListener<L> {
+    /// Wraps listener, setting header timeout for the accepted connections.
+    pub fn with(listener: L, timeout: Duration) -> Self { Self { listener, timeout } }
+
+    pub fn header_timeout(&self) -> Duration { self.timeout }
+
+    pub fn into_inner(self) -> L { self.listener }
+}
+
+impl<L: NetListener> AsRawFd for ProxyListener<L> {
+    fn as_raw_fd(&self) -> RawFd { self.listener.as_raw_fd() }
+}
+
+impl<L: NetListener> NetListener for ProxyListener<L>
+where <L::Stream as NetConnection>::Addr: From<SocketAddr>
+{
+    type Stream = Proxied<L::Stream>;
+    type Addr = L::Addr;
+
+    fn bind(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
+    where Self: Sized {
+        L::bind(addr).map(|listener| Self::with(listener, PROXY_HEADER_TIMEOUT))
+    }
+
+    #[cfg(feature = "nonblocking")]
+    fn bind_reusable(addr: &impl ToListenerAddr<Self::Addr>) -> io::Result<Self>
+    where Self: Sized {
+        L::bind_reusable(addr).map(|listener| Self::with(listener, PROXY_HEADER_TIMEOUT))
+    }
+
+    #[cfg(feature = "nonblocking")]
+    fn bind_with_options(
+        addr: &impl ToListenerAddr<Self::Addr>,
+        options: &ListenerOptions,
+    ) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        L::bind_with_options(addr, options)
+            .map(|listener| Self::with(listener, PROXY_HEADER_TIMEOUT))
+    }
+
+    fn from_listening_fd(fd: OwnedFd) -> io::Result<Self>
+    where Self: Sized {
+        L::from_listening_fd(fd).map(|listener| Self::with(listener, PROXY_HEADER_TIMEOUT))
+    }
+
+    fn accept(&self) -> io::Result<Self::Stream> {
+        let connection = self.listener.accept()?;
+        Proxied::accept(connection, self.timeout)
+    }
+
+    fn try_accept(
+        &self,
+    ) -> Result<Self::Stream, AcceptError<<Self::Stream as NetConnection>::Addr>> {
+        let connection = self.listener.accept()?;
+        let peer = connection.remote_addr()?;
+        Proxied::accept(connection, self.timeout).map_err(|err| {
+            AcceptError::Rejected(peer, Rejection::Other(format!("invalid PROXY header: {err}")))
+        })
+    }
+
+    fn local_addr(&self) -> Self::Addr { self.listener.local_addr() }
+
+    fn ttl(&self) -> io::Result<u32> { self.listener.ttl() }
+
+    fn set_ttl(&self, ttl: u32) -> io::Result<()> { self.listener.set_ttl(ttl) }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.listener.set_nonblocking(nonblocking)
+    }
+
+    fn try_clone(&self) -> io::Result<Self>
+    where Self: Sized {
+        Ok(Self::with(self.listener.try_clone()?, self.timeout))
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> { self.listener.take_error() }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "reactor")]
+    use std::net::IpAddr;
+
+    #[cfg(feature = "reactor")]
+    use reactor::{Io, Resource};
+
+    use super::*;
+    #[cfg(feature = "reactor")]
+    use crate::loopback::Loopback;
+    #[cfg(feature = "reactor")]
+    use crate::resource::{NetTransport, SessionEvent};
+
+    fn v2(version_command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([version_command, family]);
+        header.extend((addrs.len() as u16).to_be_bytes());
+        header.extend(addrs);
+        header
+    }
+
+    fn proxied(source: &str, destination: &str) -> ProxyHeader {
+        ProxyHeader::Proxied {
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        assert_eq!(
+            ProxyHeader::parse_v1(b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443"),
+            Ok(proxied("192.168.0.1:56324", "10.0.0.1:443"))
+        );
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        assert_eq!(
+            ProxyHeader::parse_v1(b"PROXY TCP6 2001:db8::1 ::1 56324 443"),
+            Ok(proxied("[2001:db8::1]:56324", "[::1]:443"))
+        );
+    }
+
+    #[test]
+    fn v1_unknown() {
+        assert_eq!(ProxyHeader::parse_v1(b"PROXY UNKNOWN"), Ok(ProxyHeader::Unknown));
+        assert_eq!(
+            ProxyHeader::parse_v1(b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535"),
+            Ok(ProxyHeader::Unknown)
+        );
+    }
+
+    #[test]
+    fn v1_invalid() {
+        for line in [
+            &b"PROXY TCP4 192.168.0.1 10.0.0.1 56324"[..],
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443 0",
+            b"PROXY TCP4 ::1 ::1 56324 443",
+            b"PROXY TCP6 192.168.0.1 10.0.0.1 56324 443",
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 65536",
+            b"PROXY UDP4 192.168.0.1 10.0.0.1 56324 443",
+            b"PROXY",
+            b"proxy TCP4 192.168.0.1 10.0.0.1 56324 443",
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 \xFF",
+        ] {
+            assert_eq!(ProxyHeader::parse_v1(line), Err(ProxyHeaderError::InvalidV1));
+        }
+    }
+
+    #[test]
+    fn v2_ipv4() {
+        let addrs = [192, 168, 0, 1, 10, 0, 0, 1, 0xDC, 0x04, 0x01, 0xBB];
+        assert_eq!(
+            ProxyHeader::parse_v2(0x21, 0x11, &addrs),
+            Ok(proxied("192.168.0.1:56324", "10.0.0.1:443"))
+        );
+        // TLVs following the addresses are ignored
+        let mut addrs = addrs.to_vec();
+        addrs.extend([0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            ProxyHeader::parse_v2(0x21, 0x11, &addrs),
+            Ok(proxied("192.168.0.1:56324", "10.0.0.1:443"))
+        );
+    }
+
+    #[test]
+    fn v2_ipv6() {
+        let mut addrs = Ipv6Addr::LOCALHOST.octets().to_vec();
+        addrs.extend(Ipv6Addr::UNSPECIFIED.octets());
+        addrs.extend([0xDC, 0x04, 0x01, 0xBB]);
+        assert_eq!(
+            ProxyHeader::parse_v2(0x21, 0x21, &addrs),
+            Ok(proxied("[::1]:56324", "[::]:443"))
+        );
+    }
+
+    #[test]
+    fn v2_local_unknown() {
+        assert_eq!(ProxyHeader::parse_v2(0x20, 0x00, &[]), Ok(ProxyHeader::Local));
+        assert_eq!(ProxyHeader::parse_v2(0x21, 0x00, &[]), Ok(ProxyHeader::Unknown));
+        assert_eq!(ProxyHeader::parse_v2(0x21, 0x31, &[0u8; 216]), Ok(ProxyHeader::Unknown));
+    }
+
+    #[test]
+    fn v2_invalid() {
+        assert_eq!(
+            ProxyHeader::parse_v2(0x11, 0x11, &[0u8; 12]),
+            Err(ProxyHeaderError::VersionNotSupported(1))
+        );
+        assert_eq!(
+            ProxyHeader::parse_v2(0x22, 0x11, &[0u8; 12]),
+            Err(ProxyHeaderError::InvalidCommand(0x22))
+        );
+    }
+
+    #[test]
+    fn v2_truncated() {
+        assert_eq!(
+            ProxyHeader::parse_v2(0x21, 0x11, &[0u8; 11]),
+            Err(ProxyHeaderError::InvalidAddrLen)
+        );
+        assert_eq!(
+            ProxyHeader::parse_v2(0x21, 0x21, &[0u8; 35]),
+            Err(ProxyHeaderError::InvalidAddrLen)
+        );
+    }
+
+    #[test]
+    fn read_v1() {
+        let mut stream = &b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\nGET /"[..];
+        let header = ProxyHeader::read(&mut stream).unwrap();
+        assert_eq!(header.source(), Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(stream, b"GET /");
+    }
+
+    #[test]
+    fn read_v2() {
+        let mut data = v2(0x21, 0x11, &[192, 168, 0, 1, 10, 0, 0, 1, 0xDC, 0x04, 0x01, 0xBB]);
+        data.extend(b"GET /");
+        let mut stream = data.as_slice();
+        let header = ProxyHeader::read(&mut stream).unwrap();
+        assert_eq!(header, proxied("192.168.0.1:56324", "10.0.0.1:443"));
+        assert_eq!(stream, b"GET /");
+
+        let data = v2(0x20, 0x00, &[]);
+        assert_eq!(ProxyHeader::read(&mut data.as_slice()).unwrap(), ProxyHeader::Local);
+    }
+
+    #[test]
+    fn read_truncated() {
+        for data in [
+            &b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r"[..],
+            b"PROXY",
+            &v2(0x21, 0x11, &[0u8; 12])[..20],
+            &V2_SIGNATURE[..],
+        ] {
+            let err = ProxyHeader::read(&mut &data[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn read_oversized() {
+        let mut line = b"PROXY UNKNOWN ".to_vec();
+        line.extend([b'f'; V1_MAX_LEN]);
+        line.extend(b"\r\n");
+        let err = ProxyHeader::read(&mut line.as_slice()).unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<ProxyHeaderError>().unwrap();
+        assert_eq!(*err, ProxyHeaderError::TooLong);
+
+        // The longest allowed header
+        let mut line = b"PROXY UNKNOWN ".to_vec();
+        line.extend([b'f'; V1_MAX_LEN - 16]);
+        line.extend(b"\r\n");
+        assert_eq!(line.len(), V1_MAX_LEN);
+        assert_eq!(ProxyHeader::read(&mut line.as_slice()).unwrap(), ProxyHeader::Unknown);
+    }
+
+    #[test]
+    fn read_no_header() {
+        let err = ProxyHeader::read(&mut &b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn listener() {
+        use std::net::{TcpListener, TcpStream};
+
+        let listener = ProxyListener::with(
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            Duration::from_millis(100),
+        );
+        let addr = listener.local_addr();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n").unwrap();
+        let connection = listener.try_accept().unwrap();
+        assert_eq!(connection.header(), proxied("192.168.0.1:56324", "192.168.0.11:443"));
+
+        // Connections without the header are rejected, either at once or once
+        // the timeout expires
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let silent = TcpStream::connect(addr).unwrap();
+        for client in [client, silent] {
+            match listener.try_accept() {
+                Err(AcceptError::Rejected(peer, Rejection::Other(_))) => {
+                    assert_eq!(peer, client.local_addr().unwrap().into())
+                }
+                res => panic!("rejection expected, got {res:?}"),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "reactor")]
+    fn session() {
+        let (mut a, mut b) = Loopback::pair().unwrap();
+        // Accepted connections are switched into non-blocking mode by `NetAccept`
+        a.set_nonblocking(true).unwrap();
+        let mut transport = NetTransport::accept(ProxyHeaderSession::new(a)).unwrap();
+
+        let data = b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\nping";
+        b.write_all(&data[..20]).unwrap();
+        // The header is read as the data arrive, with no blocking on its
+        // incomplete part
+        for _ in 0..10 {
+            assert!(transport.handle_io(Io::Read).is_none());
+        }
+        b.write_all(&data[20..]).unwrap();
+        for _ in 0..data.len() - 4 - 20 - 1 {
+            assert!(transport.handle_io(Io::Read).is_none());
+        }
+        match transport.handle_io(Io::Read) {
+            Some(SessionEvent::Established(_, artifact)) => {
+                assert_eq!(
+                    artifact.state.source().map(|addr| addr.ip()),
+                    Some(IpAddr::from([192, 168, 0, 1]))
+                )
+            }
+            _ => panic!("established event expected"),
+        }
+        match transport.handle_io(Io::Read) {
+            Some(SessionEvent::Data(data)) => assert_eq!(data, b"ping"),
+            _ => panic!("data event expected"),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// Library for building scalable privacy-preserving microservices P2P nodes
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2022-2023 by
+//     Dr. Maxim Orlovsky <orlovsky@cyphernet.org>
+//
+// Copyright 2022-2023 Cyphernet DAO, Switzerland
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Proxy protocols implemented as session state machines (see
+//! [`crate::NetStateMachine`]).
+
+pub mod haproxy;
+pub mod http;
+pub mod socks5;
+
+use std::fmt::{self, Debug, Formatter};
+
+use cyphernet::addr::{Host, HostName, NetAddr};
+pub use haproxy::{
+    Proxied, ProxyHeader, ProxyHeaderError, ProxyHeaderParser, ProxyHeaderSession, ProxyListener,
+};
+pub use http::{HttpConnect, HttpConnectError, HttpConnectSession};
+pub use socks5::{
+    Socks5Client, Socks5ClientError, Socks5Server, Socks5ServerError, Socks5ServerSession,
+};
+
+use crate::session::ZeroInit;
+use crate::{HandshakeErrorKind, NetProtocol, NetStateMachine};
+
+/// Session connecting to a remote peer directly or via a proxy.
+pub type ProxySession<S> = NetProtocol<ProxyClient, S>;
+
+/// Username and password for authenticating with a proxy.
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub struct ProxyCredentials {
+    username: String,
+    password: String,
+}
+
+impl Debug for ProxyCredentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyCredentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl ProxyCredentials {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    pub fn username(&self) -> &str { &self.username }
+    pub fn password(&self) -> &str { &self.password }
+}
+
+/// Protocol used for connecting via proxy.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Display)]
+pub enum ProxyProtocol {
+    /// SOCKS5 proxy.
+    #[default]
+    #[display("SOCKS5")]
+    Socks5,
+
+    /// HTTP proxy supporting `CONNECT` method.
+    #[display("HTTP CONNECT")]
+    HttpConnect,
+}
+
+/// Errors of the proxy handshake.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(inner)]
+pub enum ProxyError {
+    #[from]
+    Socks5(Socks5ClientError),
+
+    #[from]
+    Http(HttpConnectError),
+}
+
+/// Client state machine connecting to a remote address either directly or via
+/// a proxy speaking one of the [`ProxyProtocol`]s.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ProxyClient {
+    /// Direct connection to the remote address, requiring no handshake.
+    Direct(NetAddr<HostName>),
+
+    /// Connection via SOCKS5 proxy.
+    Socks5(Socks5Client),
+
+    /// Connection via HTTP proxy.
+    Http(HttpConnect),
+}
+
+impl ProxyClient {
+    /// Constructs state machine for connecting to the `addr` using the given
+    /// proxy `protocol`. Unless the proxy is forced, addresses which do not
+    /// require proxy (see [`Host::requires_proxy`]) are connected directly.
+    pub fn with(
+        protocol: ProxyProtocol,
+        addr: impl Into<NetAddr<HostName>>,
+        force_proxy: bool,
+        credentials: Option<ProxyCredentials>,
+    ) -> Self {
+        let addr = addr.into();
+        if !force_proxy && !addr.requires_proxy() {
+            return ProxyClient::Direct(addr);
+        }
+        match (protocol, credentials) {
+            (ProxyProtocol::Socks5, None) => ProxyClient::Socks5(Socks5Client::with(addr, true)),
+            (ProxyProtocol::Socks5, Some(credentials)) => {
+                ProxyClient::Socks5(Socks5Client::with(addr, true).with_credentials(credentials))
+            }
+            (ProxyProtocol::HttpConnect, None) => ProxyClient::Http(HttpConnect::with(addr)),
+            (ProxyProtocol::HttpConnect, Some(credentials)) => {
+                ProxyClient::Http(HttpConnect::with(addr).with_credentials(credentials))
+            }
+        }
+    }
+
+    /// Constructs state machine for a direct connection.
+    pub fn direct(addr: impl Into<NetAddr<HostName>>) -> Self { ProxyClient::Direct(addr.into()) }
+
+    /// Returns remote address to which the connection is made.
+    pub fn remote_addr(&self) -> &NetAddr<HostName> {
+        match self {
+            ProxyClient::Direct(addr) => addr,
+            ProxyClient::Socks5(socks5) => socks5.remote_addr(),
+            ProxyClient::Http(http) => http.remote_addr(),
+        }
+    }
+
+    /// Returns proxy protocol, or `None` for a direct connection.
+    pub fn protocol(&self) -> Option<ProxyProtocol> {
+        match self {
+            ProxyClient::Direct(_) => None,
+            ProxyClient::Socks5(_) => Some(ProxyProtocol::Socks5),
+            ProxyClient::Http(_) => Some(ProxyProtocol::HttpConnect),
+        }
+    }
+}
+
+impl NetStateMachine for ProxyClient {
+    const NAME: &'static str = "proxy";
+    type Init = ZeroInit;
+    type Artifact = NetAddr<HostName>;
+    type Error = ProxyError;
+
+    fn init(&mut self, _: Self::Init) {}
+
+    fn next_read_len(&self) -> usize {
+        match self {
+            ProxyClient::Direct(_) => 0,
+            ProxyClient::Socks5(socks5) => socks5.next_read_len(),
+            ProxyClient::Http(http) => http.next_read_len(),
+        }
+    }
+
+    fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        match self {
+            ProxyClient::Direct(_) => Ok(vec![]),
+            ProxyClient::Socks5(socks5) => socks5.advance(input).map_err(ProxyError::from),
+            ProxyClient::Http(http) => http.advance(input).map_err(ProxyError::from),
+        }
+    }
+
+    fn error_kind(_: &Self::Error) -> HandshakeErrorKind { HandshakeErrorKind::ProxyFailure }
+
+    fn artifact(&self) -> Option<Self::Artifact> {
+        match self {
+            ProxyClient::Direct(addr) => Some(addr.clone()),
+            ProxyClient::Socks5(socks5) => socks5.artifact(),
+            ProxyClient::Http(http) => http.artifact(),
+        }
+    }
+
+    fn is_init(&self) -> bool { true }
+}
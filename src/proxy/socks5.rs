@@ -0,0 +1,692 @@
+// Library for building scalable privacy-preserving microservices P2P nodes
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2022-2023 by
+//     Dr. Maxim Orlovsky <orlovsky@cyphernet.org>
+//
+// Copyright 2022-2023 Cyphernet DAO, Switzerland
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client and server sides of the SOCKS5 protocol ([RFC 1928]), including
+//! username/password authentication ([RFC 1929]) on the client side.
+//!
+//! [RFC 1928]: https://www.rfc-editor.org/rfc/rfc1928
+//! [RFC 1929]: https://www.rfc-editor.org/rfc/rfc1929
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use cyphernet::addr::{Host, HostName, NetAddr};
+pub use cyphernet::proxy::socks5::ServerError;
+
+use super::ProxyCredentials;
+use crate::session::ZeroInit;
+use crate::{HandshakeErrorKind, NetProtocol, NetStateMachine};
+
+/// Session accepting SOCKS5 connections from clients.
+pub type Socks5ServerSession<S> = NetProtocol<Socks5Server, S>;
+
+const VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xFF;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDR_NOT_SUPPORTED: u8 = 0x08;
+
+fn encode_credentials(credentials: &ProxyCredentials) -> Result<Vec<u8>, Socks5ClientError> {
+    let username = credentials.username().as_bytes();
+    let password = credentials.password().as_bytes();
+    let ulen = u8::try_from(username.len())
+        .ok()
+        .filter(|len| *len > 0)
+        .ok_or(Socks5ClientError::InvalidCredentials)?;
+    let plen = u8::try_from(password.len())
+        .ok()
+        .filter(|len| *len > 0)
+        .ok_or(Socks5ClientError::InvalidCredentials)?;
+    let mut out = Vec::with_capacity(3 + ulen as usize + plen as usize);
+    out.push(AUTH_VERSION);
+    out.push(ulen);
+    out.extend(username);
+    out.push(plen);
+    out.extend(password);
+    Ok(out)
+}
+
+/// Errors of the SOCKS5 client handshake.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Socks5ClientError {
+    /// not supported SOCKS protocol version {0}
+    VersionNotSupported(u8),
+
+    /// proxy requires authentication with an unsupported method
+    AuthRequired,
+
+    /// SOCKS5 username and password must be from 1 to 255 bytes long
+    InvalidCredentials,
+
+    /// proxy has rejected provided username and password
+    AuthFailed,
+
+    /// address {0} can't be encoded in SOCKS5 request
+    AddrNotSupported(String),
+
+    /// proxy has rejected the connection: {0}
+    Server(ServerError),
+
+    /// invalid proxy reply
+    InvalidReply,
+
+    /// SOCKS5 connection is established, the handshake is complete
+    Completed,
+
+    /// connection is closed due to a failure
+    Closed,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum ClientState {
+    Initial,
+    Greeted,
+    Authenticating,
+    Requested,
+    Reading(usize),
+    Active,
+    Failed,
+}
+
+/// Client (initiator) state machine of SOCKS5 protocol handshake, which
+/// requests the proxy to connect to the remote address.
+///
+/// Unless the proxy is forced, addresses which do not require proxy (see
+/// [`Host::requires_proxy`]) are connected directly and the handshake is
+/// skipped. If credentials are provided, the client authenticates with
+/// the username and password ([RFC 1929]), which must be from 1 to 255 bytes
+/// long; otherwise it requests no authentication.
+///
+/// With Tor, which isolates circuits by the SOCKS credentials
+/// (`IsolateSOCKSAuth`), distinct credentials make connections use distinct
+/// circuits.
+///
+/// [RFC 1929]: https://www.rfc-editor.org/rfc/rfc1929
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Socks5Client {
+    addr: NetAddr<HostName>,
+    force_proxy: bool,
+    credentials: Option<ProxyCredentials>,
+    state: ClientState,
+}
+
+impl Socks5Client {
+    pub fn with(addr: impl Into<NetAddr<HostName>>, force_proxy: bool) -> Self {
+        Self {
+            addr: addr.into(),
+            force_proxy,
+            credentials: None,
+            state: ClientState::Initial,
+        }
+    }
+
+    /// Sets username and password for authenticating with the proxy.
+    pub fn with_credentials(mut self, credentials: ProxyCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Returns remote address requested from the proxy.
+    pub fn remote_addr(&self) -> &NetAddr<HostName> { &self.addr }
+
+    pub fn credentials(&self) -> Option<&ProxyCredentials> { self.credentials.as_ref() }
+
+    fn is_direct(&self) -> bool { !self.force_proxy && !self.addr.requires_proxy() }
+
+    pub fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Socks5ClientError> {
+        match self.state {
+            ClientState::Initial if self.is_direct() => {
+                self.state = ClientState::Active;
+                Ok(vec![])
+            }
+            ClientState::Initial => {
+                debug_assert!(input.is_empty());
+                let method = match self.credentials {
+                    Some(_) => METHOD_PASSWORD,
+                    None => METHOD_NO_AUTH,
+                };
+                self.state = ClientState::Greeted;
+                Ok(vec![VERSION, 0x01, method])
+            }
+            ClientState::Greeted => {
+                debug_assert_eq!(input.len(), 2);
+                if input[0] != VERSION {
+                    return self.fail(Socks5ClientError::VersionNotSupported(input[0]));
+                }
+                match (input[1], &self.credentials) {
+                    (METHOD_NO_AUTH, None) => self.request(),
+                    (METHOD_PASSWORD, Some(credentials)) => {
+                        let out = match encode_credentials(credentials) {
+                            Ok(out) => out,
+                            Err(err) => return self.fail(err),
+                        };
+                        self.state = ClientState::Authenticating;
+                        Ok(out)
+                    }
+                    _ => self.fail(Socks5ClientError::AuthRequired),
+                }
+            }
+            ClientState::Authenticating => {
+                debug_assert_eq!(input.len(), 2);
+                if input[0] != AUTH_VERSION {
+                    return self.fail(Socks5ClientError::InvalidReply);
+                }
+                if input[1] != 0x00 {
+                    return self.fail(Socks5ClientError::AuthFailed);
+                }
+                self.request()
+            }
+            ClientState::Requested => {
+                debug_assert_eq!(input.len(), 5);
+                if input[0] != VERSION {
+                    return self.fail(Socks5ClientError::VersionNotSupported(input[0]));
+                }
+                if input[1] != REPLY_SUCCEEDED {
+                    return self.fail(Socks5ClientError::Server(ServerError::from(input[1])));
+                }
+                // We do not use bound address, but need to read it out of the
+                // stream; the first byte of it is already read.
+                let len = match input[3] {
+                    ATYP_IPV4 => 4 + 2 - 1,
+                    ATYP_IPV6 => 16 + 2 - 1,
+                    ATYP_DOMAIN => input[4] as usize + 2,
+                    _ => return self.fail(Socks5ClientError::InvalidReply),
+                };
+                self.state = ClientState::Reading(len);
+                Ok(vec![])
+            }
+            ClientState::Reading(_) => {
+                self.state = ClientState::Active;
+                Ok(vec![])
+            }
+            ClientState::Active => Err(Socks5ClientError::Completed),
+            ClientState::Failed => Err(Socks5ClientError::Closed),
+        }
+    }
+
+    pub fn next_read_len(&self) -> usize {
+        match self.state {
+            ClientState::Initial => 0,
+            ClientState::Greeted | ClientState::Authenticating => 2,
+            ClientState::Requested => 5,
+            ClientState::Reading(len) => len,
+            ClientState::Active | ClientState::Failed => 0,
+        }
+    }
+
+    /// Checks whether the connection to the remote address is established,
+    /// either via proxy or directly.
+    pub fn is_active(&self) -> bool {
+        self.state == ClientState::Active
+            || (self.state == ClientState::Initial && self.is_direct())
+    }
+
+    fn request(&mut self) -> Result<Vec<u8>, Socks5ClientError> {
+        let mut out = vec![VERSION, CMD_CONNECT, 0x00];
+        match &self.addr.host {
+            HostName::Ip(IpAddr::V4(ip)) => {
+                out.push(ATYP_IPV4);
+                out.extend(ip.octets());
+            }
+            HostName::Ip(IpAddr::V6(ip)) => {
+                out.push(ATYP_IPV6);
+                out.extend(ip.octets());
+            }
+            host => {
+                let name = host.to_string();
+                let Ok(len) = u8::try_from(name.len()) else {
+                    return self.fail(Socks5ClientError::AddrNotSupported(self.addr.to_string()));
+                };
+                out.push(ATYP_DOMAIN);
+                out.push(len);
+                out.extend(name.as_bytes());
+            }
+        }
+        out.extend(self.addr.port.to_be_bytes());
+        self.state = ClientState::Requested;
+        Ok(out)
+    }
+
+    fn fail(&mut self, err: Socks5ClientError) -> Result<Vec<u8>, Socks5ClientError> {
+        self.state = ClientState::Failed;
+        Err(err)
+    }
+}
+
+impl NetStateMachine for Socks5Client {
+    const NAME: &'static str = "socks5";
+    type Init = ZeroInit;
+    type Artifact = NetAddr<HostName>;
+    type Error = Socks5ClientError;
+
+    fn init(&mut self, _: Self::Init) {}
+
+    fn next_read_len(&self) -> usize { self.next_read_len() }
+
+    fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Self::Error> { self.advance(input) }
+
+    fn error_kind(_: &Self::Error) -> HandshakeErrorKind { HandshakeErrorKind::ProxyFailure }
+
+    fn artifact(&self) -> Option<Self::Artifact> {
+        if self.is_active() {
+            Some(self.addr.clone())
+        } else {
+            None
+        }
+    }
+
+    fn is_init(&self) -> bool { true }
+}
+
+/// Errors of the SOCKS5 server handshake.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum Socks5ServerError {
+    /// not supported SOCKS protocol version {0}
+    VersionNotSupported(u8),
+
+    /// client doesn't support authentication without credentials
+    NoAcceptableMethods,
+
+    /// not supported SOCKS command {0:#04x}
+    CommandNotSupported(u8),
+
+    /// not supported address type {0:#04x}
+    AddrNotSupported(u8),
+
+    /// invalid requested address '{0}'
+    InvalidAddr(String),
+
+    /// SOCKS5 handshake is complete
+    Completed,
+}
+
+/// Server (responder) state machine of SOCKS5 protocol handshake.
+///
+/// The state machine accepts clients which do not require authentication and
+/// supports only `CONNECT` command. Once the client request is parsed, the
+/// machine replies with success and exposes the requested address as its
+/// artifact; it's up to the server to connect to the address (for instance,
+/// using a [`crate::tunnel::Tunnel`]) and to disconnect the client if this is
+/// not possible.
+///
+/// If the client request can't be served, the machine replies with a
+/// corresponding SOCKS5 failure code and fails the handshake, so the
+/// [`crate::NetTransport`] terminates once the reply is sent.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub enum Socks5Server {
+    /// Awaiting client greeting with the protocol version and the number of
+    /// supported authentication methods.
+    #[default]
+    Greeting,
+
+    /// Awaiting the list of the given number of supported authentication
+    /// methods.
+    Methods(u8),
+
+    /// Awaiting request header with the command and the address type.
+    Request,
+
+    /// Awaiting length of the requested domain name.
+    DomainLen,
+
+    /// Awaiting requested address of the given type and length, followed by
+    /// the port number.
+    Address(u8, usize),
+
+    /// The handshake is complete; the client has requested connection to the
+    /// address.
+    Active(NetAddr<HostName>),
+
+    /// The handshake has failed; the failure reply was sent to the client.
+    Failed(Socks5ServerError),
+}
+
+impl Socks5Server {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Socks5ServerError> {
+        match self {
+            Socks5Server::Greeting => {
+                debug_assert_eq!(input.len(), 2);
+                if input[0] != VERSION {
+                    return self.fail(Socks5ServerError::VersionNotSupported(input[0]));
+                }
+                if input[1] == 0 {
+                    return self.reject(
+                        vec![VERSION, METHOD_NONE_ACCEPTABLE],
+                        Socks5ServerError::NoAcceptableMethods,
+                    );
+                }
+                *self = Socks5Server::Methods(input[1]);
+                Ok(vec![])
+            }
+            Socks5Server::Methods(_) => {
+                if !input.contains(&METHOD_NO_AUTH) {
+                    return self.reject(
+                        vec![VERSION, METHOD_NONE_ACCEPTABLE],
+                        Socks5ServerError::NoAcceptableMethods,
+                    );
+                }
+                *self = Socks5Server::Request;
+                Ok(vec![VERSION, METHOD_NO_AUTH])
+            }
+            Socks5Server::Request => {
+                debug_assert_eq!(input.len(), 4);
+                if input[0] != VERSION {
+                    return self.fail(Socks5ServerError::VersionNotSupported(input[0]));
+                }
+                if input[1] != CMD_CONNECT {
+                    return self.reject(
+                        reply(REPLY_COMMAND_NOT_SUPPORTED),
+                        Socks5ServerError::CommandNotSupported(input[1]),
+                    );
+                }
+                *self = match input[3] {
+                    ATYP_IPV4 => Socks5Server::Address(ATYP_IPV4, 4),
+                    ATYP_IPV6 => Socks5Server::Address(ATYP_IPV6, 16),
+                    ATYP_DOMAIN => Socks5Server::DomainLen,
+                    unknown => {
+                        return self.reject(
+                            reply(REPLY_ADDR_NOT_SUPPORTED),
+                            Socks5ServerError::AddrNotSupported(unknown),
+                        )
+                    }
+                };
+                Ok(vec![])
+            }
+            Socks5Server::DomainLen => {
+                debug_assert_eq!(input.len(), 1);
+                *self = Socks5Server::Address(ATYP_DOMAIN, input[0] as usize);
+                Ok(vec![])
+            }
+            Socks5Server::Address(atyp, len) => {
+                debug_assert_eq!(input.len(), *len + 2);
+                let (addr, port) = input.split_at(*len);
+                let host = match *atyp {
+                    ATYP_IPV4 => {
+                        let octets = <[u8; 4]>::try_from(addr).expect("fixed length");
+                        HostName::Ip(Ipv4Addr::from(octets).into())
+                    }
+                    ATYP_IPV6 => {
+                        let octets = <[u8; 16]>::try_from(addr).expect("fixed length");
+                        HostName::Ip(Ipv6Addr::from(octets).into())
+                    }
+                    _ => {
+                        let name = String::from_utf8_lossy(addr).to_string();
+                        match HostName::from_str(&name) {
+                            Ok(host) => host,
+                            Err(_) => {
+                                return self.reject(
+                                    reply(REPLY_ADDR_NOT_SUPPORTED),
+                                    Socks5ServerError::InvalidAddr(name),
+                                )
+                            }
+                        }
+                    }
+                };
+                let port = u16::from_be_bytes([port[0], port[1]]);
+                *self = Socks5Server::Active(NetAddr::new(host, port));
+                Ok(reply(REPLY_SUCCEEDED))
+            }
+            Socks5Server::Active(_) => Err(Socks5ServerError::Completed),
+            Socks5Server::Failed(err) => Err(err.clone()),
+        }
+    }
+
+    pub fn next_read_len(&self) -> usize {
+        match self {
+            Socks5Server::Greeting => 2,
+            Socks5Server::Methods(count) => *count as usize,
+            Socks5Server::Request => 4,
+            Socks5Server::DomainLen => 1,
+            Socks5Server::Address(_, len) => *len + 2,
+            Socks5Server::Active(_) | Socks5Server::Failed(_) => 0,
+        }
+    }
+
+    /// Returns address requested by the client, if the handshake is complete.
+    pub fn requested_addr(&self) -> Option<&NetAddr<HostName>> {
+        match self {
+            Socks5Server::Active(addr) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Fails without sending a reply, since the client doesn't speak SOCKS5.
+    fn fail(&mut self, err: Socks5ServerError) -> Result<Vec<u8>, Socks5ServerError> {
+        *self = Socks5Server::Failed(err.clone());
+        Err(err)
+    }
+
+    /// Sends failure reply to the client; the handshake fails on the next
+    /// advance.
+    fn reject(
+        &mut self,
+        reply: Vec<u8>,
+        err: Socks5ServerError,
+    ) -> Result<Vec<u8>, Socks5ServerError> {
+        *self = Socks5Server::Failed(err);
+        Ok(reply)
+    }
+}
+
+/// Constructs reply to a client request with the given code. Since the server
+/// doesn't connect to the requested address itself, the bound address is
+/// always unspecified.
+fn reply(code: u8) -> Vec<u8> { vec![VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0] }
+
+impl NetStateMachine for Socks5Server {
+    const NAME: &'static str = "socks5-server";
+    type Init = ZeroInit;
+    type Artifact = NetAddr<HostName>;
+    type Error = Socks5ServerError;
+
+    fn init(&mut self, _: Self::Init) {}
+
+    fn next_read_len(&self) -> usize { self.next_read_len() }
+
+    fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Self::Error> { self.advance(input) }
+
+    fn artifact(&self) -> Option<Self::Artifact> { self.requested_addr().cloned() }
+
+    fn is_init(&self) -> bool { true }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "reactor")]
+    use std::io::{Read, Write};
+
+    #[cfg(feature = "reactor")]
+    use reactor::{Io, Resource};
+
+    use super::*;
+    #[cfg(feature = "reactor")]
+    use crate::loopback::Loopback;
+    #[cfg(feature = "reactor")]
+    use crate::resource::{NetTransport, SessionEvent, TransportState};
+    #[cfg(feature = "reactor")]
+    use crate::HandshakeError;
+
+    fn request(atyp: u8, addr: &[u8], port: u16) -> Vec<u8> {
+        let mut msg = vec![VERSION, CMD_CONNECT, 0x00, atyp];
+        msg.extend(addr);
+        msg.extend(port.to_be_bytes());
+        msg
+    }
+
+    /// Feeds the server with the input, reading exactly the number of bytes
+    /// requested by the state machine at each step.
+    fn feed(server: &mut Socks5Server, mut input: &[u8]) -> Result<Vec<u8>, Socks5ServerError> {
+        let mut output = vec![];
+        while server.next_read_len() > 0 && !input.is_empty() {
+            let len = server.next_read_len().min(input.len());
+            let (chunk, rest) = input.split_at(len);
+            if chunk.len() < server.next_read_len() {
+                break;
+            }
+            output.extend(server.advance(chunk)?);
+            input = rest;
+        }
+        Ok(output)
+    }
+
+    fn connect(req: &[u8]) -> (Socks5Server, Vec<u8>) {
+        let mut server = Socks5Server::new();
+        let mut input = vec![VERSION, 1, METHOD_NO_AUTH];
+        input.extend(req);
+        let output = feed(&mut server, &input).unwrap();
+        (server, output)
+    }
+
+    #[test]
+    fn ipv4() {
+        let (server, output) = connect(&request(ATYP_IPV4, &[127, 0, 0, 1], 8080));
+        let mut expected = vec![VERSION, METHOD_NO_AUTH];
+        expected.extend(reply(REPLY_SUCCEEDED));
+        assert_eq!(output, expected);
+        assert_eq!(
+            server.requested_addr(),
+            Some(&NetAddr::new(HostName::Ip(Ipv4Addr::LOCALHOST.into()), 8080))
+        );
+        assert_eq!(server.next_read_len(), 0);
+    }
+
+    #[test]
+    fn ipv6() {
+        let (server, _) = connect(&request(ATYP_IPV6, &Ipv6Addr::LOCALHOST.octets(), 443));
+        assert_eq!(
+            server.requested_addr(),
+            Some(&NetAddr::new(HostName::Ip(Ipv6Addr::LOCALHOST.into()), 443))
+        );
+    }
+
+    #[test]
+    fn domain() {
+        let name = b"example.com";
+        let mut addr = vec![name.len() as u8];
+        addr.extend(name);
+        let (mut server, _) = connect(&request(ATYP_DOMAIN, &addr, 80));
+        assert_eq!(server.requested_addr().unwrap().to_string(), "example.com:80");
+        assert_eq!(server.advance(&[]), Err(Socks5ServerError::Completed));
+    }
+
+    #[test]
+    fn longest_domain() {
+        let name = "a".repeat(u8::MAX as usize);
+        let mut addr = vec![u8::MAX];
+        addr.extend(name.as_bytes());
+        let (server, _) = connect(&request(ATYP_DOMAIN, &addr, 80));
+        assert_eq!(server.requested_addr().unwrap().port, 80);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut server = Socks5Server::new();
+        let req = request(ATYP_IPV4, &[127, 0, 0, 1], 8080);
+        let mut input = vec![VERSION, 1, METHOD_NO_AUTH];
+        input.extend(&req[..req.len() - 1]);
+        feed(&mut server, &input).unwrap();
+        assert_eq!(server, Socks5Server::Address(ATYP_IPV4, 4));
+        assert_eq!(server.requested_addr(), None);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut server = Socks5Server::new();
+        assert_eq!(
+            feed(&mut server, &[0x04, 1, METHOD_NO_AUTH]),
+            Err(Socks5ServerError::VersionNotSupported(0x04))
+        );
+        assert!(matches!(server, Socks5Server::Failed(_)));
+    }
+
+    #[test]
+    fn no_methods() {
+        let mut server = Socks5Server::new();
+        let output = feed(&mut server, &[VERSION, 0]).unwrap();
+        assert_eq!(output, vec![VERSION, METHOD_NONE_ACCEPTABLE]);
+        assert_eq!(server.advance(&[]), Err(Socks5ServerError::NoAcceptableMethods));
+    }
+
+    #[test]
+    fn no_acceptable_methods() {
+        let mut server = Socks5Server::new();
+        let output = feed(&mut server, &[VERSION, 2, METHOD_PASSWORD, 0x80]).unwrap();
+        assert_eq!(output, vec![VERSION, METHOD_NONE_ACCEPTABLE]);
+        assert_eq!(server.next_read_len(), 0);
+        assert_eq!(server.advance(&[]), Err(Socks5ServerError::NoAcceptableMethods));
+    }
+
+    #[test]
+    fn unsupported_command() {
+        let mut req = request(ATYP_IPV4, &[127, 0, 0, 1], 8080);
+        req[1] = 0x02;
+        let (mut server, output) = connect(&req);
+        assert_eq!(&output[2..], reply(REPLY_COMMAND_NOT_SUPPORTED));
+        assert_eq!(server.advance(&[]), Err(Socks5ServerError::CommandNotSupported(0x02)));
+    }
+
+    #[test]
+    fn unsupported_addr() {
+        let (mut server, output) = connect(&request(0x05, &[], 0));
+        assert_eq!(&output[2..], reply(REPLY_ADDR_NOT_SUPPORTED));
+        assert_eq!(server.advance(&[]), Err(Socks5ServerError::AddrNotSupported(0x05)));
+    }
+
+    #[test]
+    #[cfg(feature = "reactor")]
+    fn rejection_terminates_transport() {
+        let (a, mut b) = Loopback::pair().unwrap();
+        let session = Socks5ServerSession::new(a);
+        let mut transport = NetTransport::accept(session).unwrap();
+
+        let mut req = vec![VERSION, 1, METHOD_NO_AUTH];
+        req.extend(request(0x05, &[], 0));
+        b.write_all(&req).unwrap();
+        // Greeting, authentication methods and the request.
+        for _ in 0..3 {
+            assert!(transport.handle_io(Io::Read).is_none());
+        }
+
+        let mut reply = [0u8; 12];
+        b.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply[2..], super::reply(REPLY_ADDR_NOT_SUPPORTED));
+
+        match transport.handle_io(Io::Write) {
+            Some(SessionEvent::Terminated(err)) => {
+                let err = err.into_inner().unwrap().downcast::<HandshakeError>().unwrap();
+                assert_eq!(err.layer, Socks5Server::NAME);
+            }
+            _ => panic!("termination expected"),
+        }
+        assert_eq!(transport.state(), TransportState::Terminated);
+    }
+}
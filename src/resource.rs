@@ -32,26 +32,133 @@
 //! [C10k]: https://en.wikipedia.org/wiki/C10k_problem
 
 use std::collections::VecDeque;
+use std::convert::Infallible;
 use std::fmt::{Debug, Display, Formatter};
 use std::io::Write;
 use std::marker::PhantomData;
-use std::net::{TcpListener, ToSocketAddrs};
-use std::os::unix::io::{AsRawFd, RawFd};
-use std::time::Duration;
-use std::{fmt, io, net};
+use std::net::{Shutdown, TcpListener};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant};
+use std::{fmt, io};
 
 use reactor::poller::IoType;
 use reactor::{Io, Resource, WriteAtomic, WriteError};
 
-use crate::{Direction, NetConnection, NetListener, NetSession, READ_BUFFER_SIZE};
+use crate::admission::{Admission, AdmissionFilter, Rejection};
+#[cfg(feature = "nonblocking")]
+use crate::listener::ListenerOptions;
+use crate::listener::{AcceptError, ToListenerAddr};
+use crate::{Direction, Frame, Marshaller, NetConnection, NetListener, NetSession};
 
-// TODO: Make these parameters configurable
-/// Socket read buffer size.
-const HEAP_BUFFER_SIZE: usize = u16::MAX as usize;
-/// Maximum time to wait when reading from a socket.
-const READ_TIMEOUT: Duration = Duration::from_secs(6);
-/// Maximum time to wait when writing to a socket.
-const WRITE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Default socket read buffer size.
+pub const HEAP_BUFFER_SIZE: usize = u16::MAX as usize;
+/// Default maximum time to wait when reading from a socket.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(6);
+/// Default maximum time to wait when writing to a socket.
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Default time for which [`NetAccept`] stops accepting connections once the
+/// process has run out of file descriptors.
+pub const EXHAUSTION_BACKOFF: Duration = Duration::from_millis(500);
+/// Recommended maximum number of connections accepted by [`NetAccept`] per
+/// readiness notification (see [`NetAccept::with_accept_batch`]).
+pub const ACCEPT_BATCH_SIZE: usize = 64;
+
+/// Configuration parameters for [`NetTransport`] resources and connections
+/// accepted by [`NetAccept`].
+///
+/// The default value matches [`HEAP_BUFFER_SIZE`], [`READ_TIMEOUT`] and
+/// [`WRITE_TIMEOUT`] constants and has no handshake and idle timeouts and no
+/// write buffer limits.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TransportConfig {
+    /// Size of the heap buffer used for reading data from a socket. Must not be
+    /// zero; transports and listeners reject such configuration with
+    /// [`io::ErrorKind::InvalidInput`] error.
+    pub read_buffer_size: usize,
+    /// Maximum time to wait when reading from a socket.
+    pub read_timeout: Duration,
+    /// Maximum time to wait when writing to a socket.
+    pub write_timeout: Duration,
+    /// Maximum time for establishing the connection and completing the session
+    /// handshake, counting from the transport construction. Transports which
+    /// exceed it are terminated with [`SessionEvent::HandshakeTimeout`].
+    ///
+    /// The reactor doesn't poll resources on timers, so a [`reactor::Handler`]
+    /// must set a timer with [`reactor::Action::SetTimer`] for this duration
+    /// upon transport registration; this wakes the reactor up and makes it
+    /// deliver the timeout event.
+    ///
+    /// A socket which is still connecting (in [`TransportState::Init`] state)
+    /// doesn't become ready for I/O until the operating system completes or
+    /// fails the connection attempt, so the event may be delayed up to the
+    /// system connect timeout. Handlers requiring a strict deadline should
+    /// unregister transports which haven't got [`SessionEvent::Established`]
+    /// when their timer fires.
+    pub handshake_timeout: Option<Duration>,
+    /// Time without any data received from an active session after which
+    /// [`SessionEvent::Idle`] is emitted. Requires a reactor timer, like
+    /// [`TransportConfig::handshake_timeout`].
+    pub idle_timeout: Option<Duration>,
+    /// Limits of the data buffered for writing, applying backpressure to the
+    /// producers.
+    pub write_watermarks: Option<WriteWatermarks>,
+}
+
+/// High and low watermarks for the [`NetTransport`] write buffer.
+///
+/// Once the data buffered after a write exceed the `high` watermark, the
+/// transport becomes congested and emits [`SessionEvent::Congested`];
+/// producers should stop writing to it. While the transport is congested,
+/// writes fail with [`WriteBufferFull`] error, so the buffer can't exceed the
+/// `high` watermark by more than a single write. After that, when the buffer
+/// drops to the `low` watermark or below, [`SessionEvent::Drained`] is emitted
+/// and writes are accepted again.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct WriteWatermarks {
+    pub high: usize,
+    pub low: usize,
+}
+
+/// Error returned by [`NetTransport`] writes while its write buffer is
+/// congested (see [`WriteWatermarks`]); none of the data are written.
+///
+/// The error is returned inside [`io::Error`] of [`io::ErrorKind::Other`]
+/// kind, since [`WriteAtomic`] implementations must not fail with
+/// [`io::ErrorKind::WouldBlock`]. When the write is requested by a reactor
+/// handler (with [`reactor::Action::Send`]), the error results in a transport
+/// disconnection, so handlers should stop sending data on
+/// [`SessionEvent::Congested`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display("write buffer is full ({buffered} bytes are buffered with high watermark {high})")]
+pub struct WriteBufferFull {
+    pub buffered: usize,
+    pub high: usize,
+}
+
+impl TransportConfig {
+    /// Checks that the configuration can be used by a transport, i.e. that the
+    /// read buffer is not empty (otherwise each read would look like the end of
+    /// the stream).
+    fn validate(self) -> io::Result<Self> {
+        if self.read_buffer_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "zero read buffer size"));
+        }
+        Ok(self)
+    }
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig {
+            read_buffer_size: HEAP_BUFFER_SIZE,
+            read_timeout: READ_TIMEOUT,
+            write_timeout: WRITE_TIMEOUT,
+            handshake_timeout: None,
+            idle_timeout: None,
+            write_watermarks: None,
+        }
+    }
+}
 
 /// An event happening for a [`NetAccept`] network listener and delivered to a
 /// [`reactor::Handler`].
@@ -65,6 +172,26 @@ pub enum ListenerEvent<S: NetSession> {
 
     /// Listener `accept` call has resulted in a I/O error from the OS.
     Failure(io::Error),
+
+    /// A new incoming connection from the given remote address was rejected
+    /// by one of the listener admission filters (see
+    /// [`NetAccept::with_filter`]) or by the listener itself (see
+    /// [`NetListener::try_accept`]) and closed.
+    Rejected(<S::Connection as NetConnection>::Addr, Rejection),
+
+    /// Listener `accept` call has failed since the process or the system has
+    /// run out of file descriptors (`EMFILE` or `ENFILE` error).
+    ///
+    /// The pending connection remains in the queue, so instead of retrying the
+    /// listener stops accepting connections for the backoff period provided
+    /// in the event (see [`NetAccept::with_exhaustion_backoff`]). The
+    /// application should use this time to free some descriptors, for instance
+    /// by closing idle sessions.
+    ///
+    /// The reactor doesn't poll resources on timers, so a [`reactor::Handler`]
+    /// must set a timer with [`reactor::Action::SetTimer`] for the backoff
+    /// period; this wakes the reactor up and makes it resume accepting.
+    Exhausted(io::Error, Duration),
 }
 
 /// A reactor-manageable network listener (TCP, but not limiting to) which can
@@ -79,11 +206,26 @@ pub struct NetAccept<S: NetSession, L: NetListener<Stream = S::Connection> = Tcp
     /// new sessions constructed by this listener before they are inserted into
     /// the [`reactor`] and notifications are delivered to [`reactor::Handler`].
     listener: L,
+    config: TransportConfig,
+    admission: Admission<<S::Connection as NetConnection>::Addr>,
+    exhaustion_backoff: Duration,
+    paused_until: Option<Instant>,
+    batch_size: usize,
+    queue: VecDeque<ListenerEvent<S>>,
+    waker: Option<AcceptWaker>,
     _phantom: PhantomData<S>,
 }
 
 impl<L: NetListener<Stream = S::Connection>, S: NetSession> AsRawFd for NetAccept<S, L> {
-    fn as_raw_fd(&self) -> RawFd { self.listener.as_raw_fd() }
+    /// Returns the listener socket or, if the listener accepts connections in
+    /// batches, the descriptor which is readable when there are either pending
+    /// connections or queued events.
+    fn as_raw_fd(&self) -> RawFd {
+        match &self.waker {
+            Some(waker) => waker.as_raw_fd(),
+            None => self.listener.as_raw_fd(),
+        }
+    }
 }
 
 impl<L: NetListener<Stream = S::Connection>, S: NetSession> io::Write for NetAccept<S, L> {
@@ -112,36 +254,228 @@ impl<L: NetListener<Stream = S::Connection>, S: NetSession> NetAccept<S, L> {
     /// specific transport layer and are automatically injected into the
     /// new sessions constructed by this listener before they are inserted into
     /// the [`reactor`] and notifications are delivered to [`reactor::Handler`].
-    pub fn bind(addr: &impl ToSocketAddrs) -> io::Result<Self> {
-        let listener = L::bind(addr)?;
-        listener.set_nonblocking(true)?;
-        Ok(Self {
-            listener,
-            _phantom: default!(),
-        })
+    pub fn bind(addr: &impl ToListenerAddr<L::Addr>) -> io::Result<Self> {
+        Self::bind_with_config(addr, default!())
+    }
+
+    /// Binds listener to the provided socket address(es), applying read and
+    /// write timeouts from the `config` to all accepted connections.
+    pub fn bind_with_config(
+        addr: &impl ToListenerAddr<L::Addr>,
+        config: TransportConfig,
+    ) -> io::Result<Self> {
+        let config = config.validate()?;
+        Self::with_listener(L::bind(addr)?, config)
     }
 
     /// Binds listener to the provided socket address(es) with a given context. Same as
     /// [`NetAccept::bind`] except that it uses `SO_REUSEADDR`/`SO_REUSEPORT` to enable more
     /// sockets to bound to the same address.
     #[cfg(feature = "nonblocking")]
-    pub fn bind_reusable(addr: &impl ToSocketAddrs) -> io::Result<Self> {
-        let listener = L::bind_reusable(addr)?;
+    pub fn bind_reusable(addr: &impl ToListenerAddr<L::Addr>) -> io::Result<Self> {
+        Self::bind_reusable_with_config(addr, default!())
+    }
+
+    /// Binds reusable listener to the provided socket address(es), applying
+    /// read and write timeouts from the `config` to all accepted connections.
+    /// See [`NetAccept::bind_reusable`] for the details.
+    #[cfg(feature = "nonblocking")]
+    pub fn bind_reusable_with_config(
+        addr: &impl ToListenerAddr<L::Addr>,
+        config: TransportConfig,
+    ) -> io::Result<Self> {
+        let config = config.validate()?;
+        Self::with_listener(L::bind_reusable(addr)?, config)
+    }
+
+    /// Binds listener to the provided socket address(es), applying socket
+    /// `options` to the listener and read and write timeouts from the `config`
+    /// to all accepted connections.
+    #[cfg(feature = "nonblocking")]
+    pub fn bind_with_options(
+        addr: &impl ToListenerAddr<L::Addr>,
+        options: &ListenerOptions,
+        config: TransportConfig,
+    ) -> io::Result<Self> {
+        let config = config.validate()?;
+        Self::with_listener(L::bind_with_options(addr, options)?, config)
+    }
+
+    /// Constructs listener from an already bound and listening socket, like
+    /// the ones passed to the process by the service manager with socket
+    /// activation (see [`crate::activation::listen_fds`]), applying read and
+    /// write timeouts from the `config` to all accepted connections.
+    pub fn with_listening_fd(fd: OwnedFd, config: TransportConfig) -> io::Result<Self> {
+        let config = config.validate()?;
+        Self::with_listener(L::from_listening_fd(fd)?, config)
+    }
+
+    /// Constructs listener from a bound one, switching it into non-blocking
+    /// mode. The `config` must be already validated.
+    fn with_listener(listener: L, config: TransportConfig) -> io::Result<Self> {
         listener.set_nonblocking(true)?;
         Ok(Self {
             listener,
+            config,
+            admission: default!(),
+            exhaustion_backoff: EXHAUSTION_BACKOFF,
+            paused_until: None,
+            batch_size: 1,
+            queue: empty!(),
+            waker: None,
             _phantom: default!(),
         })
     }
 
-    /// Returns the local [`net::SocketAddr`] on which listener accepts
+    /// Returns the local address on which listener accepts connections.
+    pub fn local_addr(&self) -> L::Addr { self.listener.local_addr() }
+
+    /// Returns transport configuration used by the listener for the accepted
     /// connections.
-    pub fn local_addr(&self) -> net::SocketAddr { self.listener.local_addr() }
+    pub fn config(&self) -> TransportConfig { self.config }
+
+    /// Adds admission filter, which is consulted for each accepted connection
+    /// after the filters added before. Connections rejected by the filter are
+    /// closed and reported with [`ListenerEvent::Rejected`].
+    pub fn add_filter(
+        &mut self,
+        filter: impl AdmissionFilter<<S::Connection as NetConnection>::Addr> + 'static,
+    ) {
+        self.admission.push(filter)
+    }
 
-    fn handle_accept(&mut self) -> io::Result<S::Connection> {
-        let mut connection = self.listener.accept()?;
-        connection.set_read_timeout(Some(READ_TIMEOUT))?;
-        connection.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    /// Adds admission filter; see [`NetAccept::add_filter`] for the details.
+    pub fn with_filter(
+        mut self,
+        filter: impl AdmissionFilter<<S::Connection as NetConnection>::Addr> + 'static,
+    ) -> Self {
+        self.add_filter(filter);
+        self
+    }
+
+    /// Sets the time for which the listener stops accepting connections once
+    /// the process has run out of file descriptors. Defaults to
+    /// [`EXHAUSTION_BACKOFF`]. See [`ListenerEvent::Exhausted`] for the
+    /// details.
+    pub fn with_exhaustion_backoff(mut self, backoff: Duration) -> Self {
+        self.exhaustion_backoff = backoff;
+        self
+    }
+
+    /// Makes the listener accept up to `batch_size` connections per readiness
+    /// notification, until the listener would block or the process runs out
+    /// of file descriptors, saving a reactor poll round-trip per connection
+    /// under connection bursts. Defaults to a single connection; see
+    /// [`ACCEPT_BATCH_SIZE`] for a recommended batch size. Errors with
+    /// [`io::ErrorKind::InvalidInput`] if the `batch_size` is zero.
+    ///
+    /// Each of the connections accepted at once is still reported with its own
+    /// [`ListenerEvent`]: the events are queued in the listener and delivered
+    /// one per [`Resource::handle_io`] call, in the order the connections were
+    /// accepted. To make the reactor call the listener while the queue is not
+    /// empty, the listener registers itself in the reactor with a separate
+    /// `epoll` descriptor (see [`NetAccept::as_raw_fd`]), hence the batch size
+    /// must be set before the listener is registered in the reactor.
+    ///
+    /// Batches are supported on Linux and Android only; on other platforms the
+    /// listener accepts a single connection per notification.
+    pub fn with_accept_batch(mut self, batch_size: usize) -> io::Result<Self> {
+        if batch_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "zero accept batch size"));
+        }
+        if batch_size > 1 && AcceptWaker::IS_SUPPORTED && self.waker.is_none() {
+            self.waker = Some(AcceptWaker::with(self.listener.as_raw_fd())?);
+        }
+        if self.waker.is_some() {
+            self.batch_size = batch_size;
+        }
+        Ok(self)
+    }
+
+    /// Returns maximum number of connections accepted per readiness
+    /// notification.
+    pub fn batch_size(&self) -> usize { self.batch_size }
+
+    /// Returns the time remaining until the listener resumes accepting
+    /// connections, if it was paused due to file descriptor exhaustion.
+    pub fn paused_for(&self) -> Option<Duration> {
+        self.paused_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    fn handle_accept(&mut self) -> ListenerEvent<S> {
+        let connection = match self.listener.try_accept() {
+            Ok(connection) => connection,
+            #[allow(unused_variables)]
+            Err(AcceptError::Rejected(addr, reason)) => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "listener", "Listener has rejected incoming connection from {addr}: {reason}");
+                return ListenerEvent::Rejected(addr, reason);
+            }
+            Err(AcceptError::Io(err))
+                if matches!(err.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) =>
+            {
+                #[cfg(feature = "log")]
+                log::warn!(target: "listener", "Out of file descriptors ({err}), pausing accepting connections for {:?}", self.exhaustion_backoff);
+                self.paused_until = Some(Instant::now() + self.exhaustion_backoff);
+                return ListenerEvent::Exhausted(err, self.exhaustion_backoff);
+            }
+            Err(AcceptError::Io(err)) => return ListenerEvent::Failure(err),
+        };
+        if self.admission.is_empty() {
+            return match self.configure(connection) {
+                Ok(connection) => ListenerEvent::Accepted(connection),
+                Err(err) => ListenerEvent::Failure(err),
+            };
+        }
+
+        let addr = match connection.remote_addr() {
+            Ok(addr) => addr,
+            Err(err) => return ListenerEvent::Failure(err),
+        };
+        let fd = connection.as_raw_fd();
+        if let Err(reason) = self.admission.admit(&addr, fd) {
+            #[cfg(feature = "log")]
+            log::debug!(target: "listener", "Rejecting incoming connection from {addr}: {reason}");
+            return ListenerEvent::Rejected(addr, reason);
+        }
+        match self.configure(connection) {
+            Ok(connection) => ListenerEvent::Accepted(connection),
+            Err(err) => {
+                self.admission.rollback(&addr, fd);
+                ListenerEvent::Failure(err)
+            }
+        }
+    }
+
+    fn handle_batch(&mut self) -> Option<ListenerEvent<S>> {
+        if self.queue.is_empty() {
+            while self.queue.len() < self.batch_size {
+                match self.handle_accept() {
+                    ListenerEvent::Failure(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    event @ (ListenerEvent::Failure(_) | ListenerEvent::Exhausted(..)) => {
+                        self.queue.push_back(event);
+                        break;
+                    }
+                    event => self.queue.push_back(event),
+                }
+            }
+        }
+        let event = self.queue.pop_front();
+        if let Some(waker) = &mut self.waker {
+            #[allow(unused_variables)]
+            if let Err(err) = waker.set(!self.queue.is_empty()) {
+                #[cfg(feature = "log")]
+                log::error!(target: "listener", "Unable to wake the reactor for queued events: {err}");
+            }
+        }
+        event
+    }
+
+    fn configure(&self, mut connection: S::Connection) -> io::Result<S::Connection> {
+        connection.set_read_timeout(Some(self.config.read_timeout))?;
+        connection.set_write_timeout(Some(self.config.write_timeout))?;
         connection.set_nonblocking(true)?;
         Ok(connection)
     }
@@ -152,25 +486,174 @@ where S: Send
 {
     type Event = ListenerEvent<S>;
 
-    fn interests(&self) -> IoType { IoType::read_only() }
+    fn interests(&self) -> IoType {
+        match self.paused_for() {
+            Some(_) => IoType::none(),
+            None => IoType::read_only(),
+        }
+    }
 
     fn handle_io(&mut self, io: Io) -> Option<Self::Event> {
         match io {
-            Io::Read => Some(match self.handle_accept() {
-                Err(err) => ListenerEvent::Failure(err),
-                Ok(session) => ListenerEvent::Accepted(session),
-            }),
+            Io::Read if self.waker.is_some() => self.handle_batch(),
+            Io::Read => Some(self.handle_accept()),
             Io::Write => None,
         }
     }
 }
 
-/// An event happening for a [`NetTransport`] network transport and delivered to
-/// a [`reactor::Handler`].
-pub enum SessionEvent<S: NetSession> {
+/// `epoll` descriptor watching a listener socket together with an `eventfd`,
+/// which is signalled while [`NetAccept`] has queued events, such that the
+/// reactor polling the descriptor calls the listener for them even when there
+/// are no more pending connections.
+#[derive(Debug)]
+#[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+struct AcceptWaker {
+    epoll: OwnedFd,
+    event: OwnedFd,
+    signalled: bool,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl AcceptWaker {
+    const IS_SUPPORTED: bool = true;
+
+    fn with(listener: RawFd) -> io::Result<Self> {
+        use std::os::unix::io::FromRawFd;
+
+        let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epoll < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+        let event = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if event < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let event = unsafe { OwnedFd::from_raw_fd(event) };
+        for fd in [listener, event.as_raw_fd()] {
+            let mut interest = libc::epoll_event {
+                events: libc::EPOLLIN as u32,
+                u64: fd as u64,
+            };
+            if unsafe { libc::epoll_ctl(epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut interest) }
+                < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(AcceptWaker {
+            epoll,
+            event,
+            signalled: false,
+        })
+    }
+
+    fn set(&mut self, signalled: bool) -> io::Result<()> {
+        if signalled == self.signalled {
+            return Ok(());
+        }
+        let res = if signalled {
+            let value = 1u64.to_ne_bytes();
+            unsafe { libc::write(self.event.as_raw_fd(), value.as_ptr() as *const _, value.len()) }
+        } else {
+            let mut value = [0u8; 8];
+            unsafe { libc::read(self.event.as_raw_fd(), value.as_mut_ptr() as *mut _, value.len()) }
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.signalled = signalled;
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+impl AcceptWaker {
+    const IS_SUPPORTED: bool = false;
+
+    fn with(_listener: RawFd) -> io::Result<Self> { Err(io::ErrorKind::Unsupported.into()) }
+
+    fn set(&mut self, _signalled: bool) -> io::Result<()> { Ok(()) }
+}
+
+impl AsRawFd for AcceptWaker {
+    fn as_raw_fd(&self) -> RawFd { self.epoll.as_raw_fd() }
+}
+
+/// Reason of [`SessionEvent::Terminated`] event emitted once a transport closed
+/// with [`NetTransport::close`] has sent all its buffered data and shut down
+/// its connection for writing. Returned inside [`io::Error`] of
+/// [`io::ErrorKind::Other`] kind.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display("transport was gracefully closed")]
+pub struct GracefulClose;
+
+/// An event happening for a [`NetTransport`] or [`FramedTransport`] network
+/// transport and delivered to a [`reactor::Handler`].
+///
+/// The `F` type parameter is the type of frames decoded by [`FramedTransport`];
+/// for a [`NetTransport`] it is uninhabited, since the raw transport reports
+/// received data with [`SessionEvent::Data`] events instead.
+pub enum SessionEvent<S: NetSession, F = Infallible> {
     Established(RawFd, S::Artifact),
     Data(Vec<u8>),
     Terminated(io::Error),
+
+    /// Outgoing non-blocking connection has failed to establish (for instance
+    /// it was refused by the remote peer or has timed out). The transport is
+    /// terminated.
+    ///
+    /// The event is produced from the pending socket error (`SO_ERROR`)
+    /// checked once the connection reports write readiness. However, most of
+    /// the failed connections are reported by `poll` with a hang-up or error
+    /// flag, which the reactor delivers to [`reactor::Handler::handle_error`]
+    /// as [`reactor::Error::TransportDisconnect`] without calling
+    /// [`Resource::handle_io`]; in this case this event is never emitted. Thus
+    /// the handler must treat a disconnection of a transport which is still in
+    /// [`TransportState::Init`] state (as reported by [`NetTransport::state`]
+    /// of the transport returned within the error) as a connection failure as
+    /// well.
+    ConnectionFailed(io::Error),
+
+    /// The session has not completed its handshake before the deadline set by
+    /// [`TransportConfig::handshake_timeout`]. The transport is terminated.
+    HandshakeTimeout,
+
+    /// No data was received from the active session for the given time, which
+    /// exceeds [`TransportConfig::idle_timeout`]. The event is emitted once per
+    /// idle period; the transport remains active.
+    Idle(Duration),
+
+    /// Write buffer has exceeded the high watermark (see [`WriteWatermarks`]);
+    /// producers should stop writing to the transport until
+    /// [`SessionEvent::Drained`] is received, since the writes fail with
+    /// [`WriteBufferFull`] error meanwhile. The data already written are kept
+    /// in the buffer and sent.
+    Congested,
+
+    /// Write buffer of a congested transport has drained to the low watermark;
+    /// writes can be resumed.
+    Drained,
+
+    /// A complete frame received and decoded by [`FramedTransport`].
+    Frame(F),
+}
+
+impl<S: NetSession> SessionEvent<S> {
+    fn with_frames<F>(self) -> SessionEvent<S, F> {
+        match self {
+            SessionEvent::Established(fd, artifact) => SessionEvent::Established(fd, artifact),
+            SessionEvent::Data(data) => SessionEvent::Data(data),
+            SessionEvent::Terminated(err) => SessionEvent::Terminated(err),
+            SessionEvent::ConnectionFailed(err) => SessionEvent::ConnectionFailed(err),
+            SessionEvent::HandshakeTimeout => SessionEvent::HandshakeTimeout,
+            SessionEvent::Idle(duration) => SessionEvent::Idle(duration),
+            SessionEvent::Congested => SessionEvent::Congested,
+            SessionEvent::Drained => SessionEvent::Drained,
+            SessionEvent::Frame(never) => match never {},
+        }
+    }
 }
 
 /// A state of [`NetTransport`] network transport.
@@ -180,7 +663,8 @@ pub enum TransportState {
     /// This happens only for outgoing connections due to the use of
     /// non-blocking version of a `connect` sys-call. The state is switched once
     /// we receive first notification on a `write` event on this resource from
-    /// the reactor `poll`.
+    /// the reactor `poll`, unless the connection has failed (see
+    /// [`SessionEvent::ConnectionFailed`]).
     Init,
 
     /// The connection is established, but the session handshake is still in
@@ -191,6 +675,11 @@ pub enum TransportState {
     /// The session is active; all handshakes had completed.
     Active,
 
+    /// The transport is closing after a call to [`NetTransport::close`]: it
+    /// discards new writes and sends the data remaining in its write buffer,
+    /// after which it shuts down the connection for writing.
+    Closing,
+
     /// Session was terminated by any reason: local shutdown, remote orderly
     /// shutdown, connectivity issue, dropped connections, encryption or
     /// authentication problem etc. Reading and writing from the resource in
@@ -206,8 +695,16 @@ pub struct NetTransport<S: NetSession> {
     state: TransportState,
     session: S,
     link_direction: Direction,
+    config: TransportConfig,
+    handshake_deadline: Option<Instant>,
+    close_deadline: Option<Instant>,
+    last_read: Instant,
+    last_write: Instant,
+    idle_reported: bool,
+    congested: bool,
+    congestion_reported: bool,
     write_intent: bool,
-    read_buffer: Box<[u8; HEAP_BUFFER_SIZE]>,
+    read_buffer: Box<[u8]>,
     write_buffer: VecDeque<u8>,
 }
 
@@ -225,8 +722,34 @@ impl<S: NetSession> AsRawFd for NetTransport<S> {
 }
 
 impl<S: NetSession> NetTransport<S> {
-    pub fn accept(session: S) -> io::Result<Self> {
-        Self::with_state(session, TransportState::Handshake, Direction::Inbound)
+    pub fn accept(session: S) -> io::Result<Self> { Self::accept_with_config(session, default!()) }
+
+    /// Constructs reactor-managed resource around an incoming [`NetSession`]
+    /// using a custom transport configuration.
+    pub fn accept_with_config(session: S, config: TransportConfig) -> io::Result<Self> {
+        Self::with_state(session, TransportState::Handshake, Direction::Inbound, config)
+    }
+
+    /// Constructs reactor-managed resource around an outgoing [`NetSession`]
+    /// which connection was created in a non-blocking mode (using
+    /// [`NetConnection::connect_nonblocking`] or
+    /// [`NetConnection::connect_reusable_nonblocking`]).
+    ///
+    /// The transport starts in [`TransportState::Init`] state, awaiting for the
+    /// connection to be established. Connection failures are reported either
+    /// with [`SessionEvent::ConnectionFailed`] event or, more commonly, with
+    /// [`reactor::Error::TransportDisconnect`] error; see
+    /// [`SessionEvent::ConnectionFailed`] for the details.
+    pub fn connect(session: S) -> io::Result<Self> {
+        Self::connect_with_config(session, default!())
+    }
+
+    /// Constructs reactor-managed resource around an outgoing non-blocking
+    /// [`NetSession`] using a custom transport configuration. See
+    /// [`NetTransport::connect`] for the details.
+    pub fn connect_with_config(mut session: S, config: TransportConfig) -> io::Result<Self> {
+        session.as_connection_mut().set_nonblocking(true)?;
+        Self::with_state(session, TransportState::Init, Direction::Outbound, config)
     }
 
     /// Constructs reactor-managed resource around an existing [`NetSession`].
@@ -236,7 +759,37 @@ impl<S: NetSession> NetTransport<S> {
     /// # Errors
     ///
     /// If a session can be put into a non-blocking mode.
-    pub fn with_session(mut session: S, link_direction: Direction) -> io::Result<Self> {
+    pub fn with_session(session: S, link_direction: Direction) -> io::Result<Self> {
+        Self::with_session_inner(session, link_direction, default!())
+    }
+
+    /// Constructs reactor-managed resource around an existing [`NetSession`]
+    /// using a custom transport configuration. Unlike [`NetTransport::with_session`],
+    /// applies read and write timeouts from the `config` to the session
+    /// connection.
+    ///
+    /// NB: Must not be called for connections created in a non-blocking mode!
+    ///
+    /// # Errors
+    ///
+    /// If a session can be put into a non-blocking mode or timeouts can't be
+    /// set.
+    pub fn with_session_and_config(
+        mut session: S,
+        link_direction: Direction,
+        config: TransportConfig,
+    ) -> io::Result<Self> {
+        session.as_connection_mut().set_read_timeout(Some(config.read_timeout))?;
+        session.as_connection_mut().set_write_timeout(Some(config.write_timeout))?;
+        Self::with_session_inner(session, link_direction, config)
+    }
+
+    fn with_session_inner(
+        mut session: S,
+        link_direction: Direction,
+        config: TransportConfig,
+    ) -> io::Result<Self> {
+        let config = config.validate()?;
         let state = if session.is_established() {
             // If we are disconnected, we will get instantly updated from the
             // reactor and the state will change automatically
@@ -246,11 +799,19 @@ impl<S: NetSession> NetTransport<S> {
         };
         session.as_connection_mut().set_nonblocking(true)?;
         Ok(Self {
+            handshake_deadline: Self::deadline(state, config),
+            close_deadline: None,
+            last_read: Instant::now(),
+            last_write: Instant::now(),
+            idle_reported: false,
+            congested: false,
+            congestion_reported: false,
             state,
             session,
             link_direction,
+            config,
             write_intent: true,
-            read_buffer: Box::new([0u8; READ_BUFFER_SIZE]),
+            read_buffer: vec![0u8; config.read_buffer_size].into_boxed_slice(),
             write_buffer: empty!(),
         })
     }
@@ -270,6 +831,7 @@ impl<S: NetSession> NetTransport<S> {
     ///
     /// If the connection is failed and the write buffer has some data, errors
     /// with the connection failure code.
+    #[allow(clippy::result_large_err)]
     pub fn into_session(mut self) -> Result<S, (Self, io::Error)> {
         if let Err(err) = self.empty_write_buf() {
             return Err((self, err));
@@ -281,22 +843,79 @@ impl<S: NetSession> NetTransport<S> {
         mut session: S,
         state: TransportState,
         link_direction: Direction,
+        config: TransportConfig,
     ) -> io::Result<Self> {
-        session.as_connection_mut().set_read_timeout(Some(READ_TIMEOUT))?;
-        session.as_connection_mut().set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let config = config.validate()?;
+        session.as_connection_mut().set_read_timeout(Some(config.read_timeout))?;
+        session.as_connection_mut().set_write_timeout(Some(config.write_timeout))?;
         Ok(Self {
+            handshake_deadline: Self::deadline(state, config),
+            close_deadline: None,
+            last_read: Instant::now(),
+            last_write: Instant::now(),
+            idle_reported: false,
+            congested: false,
+            congestion_reported: false,
             state,
             session,
             link_direction,
+            config,
             write_intent: false,
-            read_buffer: Box::new([0u8; READ_BUFFER_SIZE]),
+            read_buffer: vec![0u8; config.read_buffer_size].into_boxed_slice(),
             write_buffer: empty!(),
         })
     }
 
+    fn deadline(state: TransportState, config: TransportConfig) -> Option<Instant> {
+        match state {
+            TransportState::Init | TransportState::Handshake => {
+                config.handshake_timeout.map(|timeout| Instant::now() + timeout)
+            }
+            TransportState::Active | TransportState::Closing | TransportState::Terminated => None,
+        }
+    }
+
+    /// Gracefully closes the transport, switching it into
+    /// [`TransportState::Closing`] state.
+    ///
+    /// The transport continues to send the data already buffered, silently
+    /// discarding all new writes (including the ones performed by the reactor
+    /// with [`reactor::Action::Send`]). Once the buffer is empty, it shuts down
+    /// the connection for writing and emits [`SessionEvent::Terminated`] with
+    /// [`GracefulClose`] reason. If the buffer can't be sent before the
+    /// [`NetTransport::close_deadline`], which is set to
+    /// [`TransportConfig::write_timeout`] from now, the transport is terminated
+    /// with [`io::ErrorKind::TimedOut`] error instead.
+    ///
+    /// The reactor doesn't poll resources on timers, so a [`reactor::Handler`]
+    /// must set a timer with [`reactor::Action::SetTimer`] for the write
+    /// timeout after closing the transport. This wakes the reactor up and makes
+    /// it deliver the timeout event; however, a connection which is not ready
+    /// for I/O may still not get polled, so if the transport has not terminated
+    /// when the timer fires, the handler should unregister it with
+    /// [`reactor::Action::UnregisterTransport`].
+    ///
+    /// Does nothing if the transport is already closing or terminated.
+    pub fn close(&mut self) {
+        if matches!(self.state, TransportState::Closing | TransportState::Terminated) {
+            return;
+        }
+        #[cfg(feature = "log")]
+        log::debug!(target: "transport", "Closing transport {self} with {} bytes buffered", self.write_buffer.len());
+
+        self.state = TransportState::Closing;
+        self.close_deadline = Some(Instant::now() + self.config.write_timeout);
+    }
+
+    /// Returns time by which a closing transport must send its buffered data
+    /// (see [`NetTransport::close`]), or `None` if the transport is not
+    /// closing.
+    pub fn close_deadline(&self) -> Option<Instant> { self.close_deadline }
+
     pub fn display(&self) -> impl Display { self.session.display() }
 
     pub fn state(&self) -> TransportState { self.state }
+    pub fn config(&self) -> TransportConfig { self.config }
     pub fn is_active(&self) -> bool { self.state == TransportState::Active }
 
     pub fn is_inbound(&self) -> bool { self.link_direction() == Direction::Inbound }
@@ -315,6 +934,40 @@ impl<S: NetSession> NetTransport<S> {
 
     pub fn write_buf_len(&self) -> usize { self.write_buffer.len() }
 
+    /// Returns the time by which the session handshake must complete, if the
+    /// handshake timeout is configured.
+    pub fn handshake_deadline(&self) -> Option<Instant> { self.handshake_deadline }
+
+    /// Checks whether the transport is still not active after the handshake
+    /// deadline.
+    pub fn is_handshake_expired(&self) -> bool {
+        matches!(self.state, TransportState::Init | TransportState::Handshake)
+            && matches!(self.handshake_deadline, Some(deadline) if Instant::now() >= deadline)
+    }
+
+    /// Returns the time when the data were last received from the session.
+    pub fn last_read(&self) -> Instant { self.last_read }
+
+    /// Returns the time when the data were last sent to the session.
+    pub fn last_write(&self) -> Instant { self.last_write }
+
+    fn is_idle(&self) -> bool {
+        self.state == TransportState::Active
+            && !self.idle_reported
+            && matches!(self.config.idle_timeout, Some(timeout) if self.last_read.elapsed() >= timeout)
+    }
+
+    /// Checks whether the write buffer has exceeded the high watermark and
+    /// hasn't drained to the low watermark since (see [`WriteWatermarks`]).
+    pub fn is_congested(&self) -> bool { self.congested }
+
+    fn is_congestion_pending(&self) -> bool { self.congested && !self.congestion_reported }
+
+    fn is_drained(&self) -> bool {
+        self.congestion_reported
+            && matches!(self.config.write_watermarks, Some(watermarks) if self.write_buffer.len() <= watermarks.low)
+    }
+
     fn terminate(&mut self, reason: io::Error) -> SessionEvent<S> {
         #[cfg(feature = "log")]
         log::trace!(target: "transport", "Terminating session {self} due to {reason:?}");
@@ -323,9 +976,49 @@ impl<S: NetSession> NetTransport<S> {
         SessionEvent::Terminated(reason)
     }
 
+    fn is_close_expired(&self) -> bool {
+        self.state == TransportState::Closing
+            && matches!(self.close_deadline, Some(deadline) if Instant::now() >= deadline)
+    }
+
+    fn handle_closing(&mut self, io: Io) -> Option<SessionEvent<S>> {
+        if io == Io::Write {
+            if let Err(err) = self.flush_buffer() {
+                return Some(self.terminate(err));
+            }
+        }
+        if !self.write_buffer.is_empty() {
+            if self.is_close_expired() {
+                #[cfg(feature = "log")]
+                log::debug!(target: "transport", "Transport {self} has not sent its buffer before closing in time");
+
+                return Some(self.terminate(io::ErrorKind::TimedOut.into()));
+            }
+            return None;
+        }
+        if let Err(err) = self
+            .session
+            .flush()
+            .and_then(|_| self.session.as_connection_mut().shutdown(Shutdown::Write))
+        {
+            return Some(self.terminate(err));
+        }
+        Some(self.terminate(io::Error::new(io::ErrorKind::Other, GracefulClose)))
+    }
+
     fn handle_writable(&mut self) -> Option<SessionEvent<S>> {
         if !self.session.is_established() {
-            let _ = self.session.write(&[]);
+            // Handshake errors are surfaced on write once the failure reply (if
+            // any) was sent, so the peer gets it before disconnection.
+            match self.session.write(&[]) {
+                Err(err)
+                    if err.kind() != io::ErrorKind::WouldBlock
+                        && err.kind() != io::ErrorKind::Interrupted =>
+                {
+                    return Some(self.terminate(err));
+                }
+                _ => {}
+            }
             self.write_intent = true;
             return None;
         }
@@ -359,7 +1052,11 @@ impl<S: NetSession> NetTransport<S> {
         match self.session.read(self.read_buffer.as_mut()) {
             Ok(0) if !self.session.is_established() => None,
             Ok(0) => Some(SessionEvent::Terminated(io::ErrorKind::ConnectionReset.into())),
-            Ok(len) => Some(SessionEvent::Data(self.read_buffer[..len].to_vec())),
+            Ok(len) => {
+                self.last_read = Instant::now();
+                self.idle_reported = false;
+                Some(SessionEvent::Data(self.read_buffer[..len].to_vec()))
+            }
             Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
                 // This shouldn't normally happen, since this function is only called
                 // when there's data on the socket. We leave it here in case external
@@ -396,14 +1093,15 @@ impl<S: NetSession> NetTransport<S> {
                     },
                 }
             })?;
-        if orig_len > len {
-            #[cfg(feature = "log")]
+        self.write_intent = orig_len > len;
+        if len > 0 {
+            self.last_write = Instant::now();
+        }
+        #[cfg(feature = "log")]
+        if self.write_intent {
             log::debug!(target: "transport", "Resource {} was able to consume only a part of the buffered data ({len} of {orig_len} bytes)", self.display());
-            self.write_intent = true;
         } else {
-            #[cfg(feature = "log")]
             log::trace!(target: "transport", "Resource {} was able to consume all of the buffered data ({len} of {orig_len} bytes)", self.display());
-            self.write_intent = false;
         }
         self.write_buffer.drain(..len);
         Ok(())
@@ -415,8 +1113,24 @@ impl<S: NetSession> Resource for NetTransport<S> {
 
     fn interests(&self) -> IoType {
         match self.state {
+            // Connected socket is always writable, so this makes the reactor
+            // call `handle_io` and deliver the timeout event. The expiry is
+            // checked before `Init`, so a connection which completes after the
+            // deadline gets timed out instead of starting the handshake.
+            TransportState::Init | TransportState::Handshake if self.is_handshake_expired() => {
+                IoType::read_write()
+            }
             TransportState::Init => IoType::write_only(),
             TransportState::Terminated => IoType::none(),
+            // Either of the events makes `handle_io` terminate the transport,
+            // whatever the socket gets ready for first.
+            TransportState::Closing if self.is_close_expired() => IoType::read_write(),
+            TransportState::Closing => IoType::write_only(),
+            TransportState::Active
+                if self.is_idle() || self.is_congestion_pending() || self.is_drained() =>
+            {
+                IoType::read_write()
+            }
             TransportState::Active | TransportState::Handshake if self.write_intent => {
                 IoType::read_write()
             }
@@ -427,8 +1141,53 @@ impl<S: NetSession> Resource for NetTransport<S> {
     fn handle_io(&mut self, io: Io) -> Option<Self::Event> {
         debug_assert_ne!(self.state, TransportState::Terminated, "I/O on terminated transport");
 
+        if self.state == TransportState::Closing {
+            return self.handle_closing(io);
+        }
+
+        if self.is_handshake_expired() {
+            #[cfg(feature = "log")]
+            log::debug!(target: "transport", "Transport {self} has not completed handshake in time");
+
+            self.state = TransportState::Terminated;
+            return Some(SessionEvent::HandshakeTimeout);
+        }
+
+        if io == Io::Write && self.is_idle() {
+            #[cfg(feature = "log")]
+            log::debug!(target: "transport", "Transport {self} is idle");
+
+            self.idle_reported = true;
+            return Some(SessionEvent::Idle(self.last_read.elapsed()));
+        }
+        if io == Io::Write && self.is_congestion_pending() {
+            #[cfg(feature = "log")]
+            log::debug!(target: "transport", "Write buffer of {self} is congested with {} bytes", self.write_buffer.len());
+
+            self.congestion_reported = true;
+            return Some(SessionEvent::Congested);
+        }
+        if io == Io::Write && self.is_drained() {
+            #[cfg(feature = "log")]
+            log::trace!(target: "transport", "Write buffer of {self} is drained");
+
+            self.congested = false;
+            self.congestion_reported = false;
+            return Some(SessionEvent::Drained);
+        }
         let mut force_write_intent = false;
         if self.state == TransportState::Init {
+            match self.session.as_connection().take_error() {
+                Ok(None) => {}
+                Ok(Some(err)) | Err(err) => {
+                    #[cfg(feature = "log")]
+                    log::debug!(target: "transport", "Transport {self} has failed to connect: {err}");
+
+                    self.state = TransportState::Terminated;
+                    return Some(SessionEvent::ConnectionFailed(err));
+                }
+            }
+
             #[cfg(feature = "log")]
             log::debug!(target: "transport", "Transport {self} is connected, initializing handshake");
 
@@ -467,6 +1226,8 @@ impl<S: NetSession> Resource for NetTransport<S> {
             // We just got connected; may need to send output
             self.write_intent = true;
             self.state = TransportState::Active;
+            self.last_read = Instant::now();
+            self.last_write = Instant::now();
             Some(SessionEvent::Established(
                 self.as_raw_fd(),
                 self.session.artifact().expect("session is established"),
@@ -493,7 +1254,9 @@ impl<S: NetSession> Write for NetTransport<S> {
 }
 
 impl<S: NetSession> WriteAtomic for NetTransport<S> {
-    fn is_ready_to_write(&self) -> bool { self.state == TransportState::Active }
+    fn is_ready_to_write(&self) -> bool {
+        matches!(self.state, TransportState::Active | TransportState::Closing)
+    }
 
     fn empty_write_buf(&mut self) -> io::Result<bool> {
         let len = self.session.write(self.write_buffer.make_contiguous())?;
@@ -509,7 +1272,562 @@ impl<S: NetSession> WriteAtomic for NetTransport<S> {
             // Write empty data is a non-op
             return Ok(());
         }
+        if self.state == TransportState::Closing {
+            #[cfg(feature = "log")]
+            log::debug!(target: "transport", "Discarding {} bytes written to closing transport {self}", buf.len());
+            return Ok(());
+        }
+        if let Some(watermarks) = self.config.write_watermarks.filter(|_| self.congested) {
+            return Err(io::Error::new(io::ErrorKind::Other, WriteBufferFull {
+                buffered: self.write_buffer.len(),
+                high: watermarks.high,
+            }));
+        }
         self.write_buffer.extend(buf);
-        self.flush_buffer()
+        let res = self.flush_buffer();
+        // The check includes the data which were just buffered, so a single
+        // write larger than the high watermark congests the transport as well
+        if matches!(self.config.write_watermarks, Some(watermarks) if self.write_buffer.len() > watermarks.high)
+        {
+            self.congested = true;
+        }
+        res
+    }
+}
+
+/// Frame-aware adaptor around [`NetTransport`], which decodes the received data
+/// into frames of type `F` and delivers them to a [`reactor::Handler`] as
+/// [`SessionEvent::Frame`] events, one per frame. All other events of the
+/// underlying transport are passed through; [`SessionEvent::Data`] is never
+/// emitted.
+///
+/// If the received data can't be decoded, the transport is terminated with
+/// [`SessionEvent::Terminated`] event containing [`io::Error`] of
+/// [`io::ErrorKind::InvalidData`] kind, which wraps the `F::Error` decoding
+/// error. Frames decoded before the failure are delivered first.
+///
+/// Frames can be sent directly with [`FramedTransport::write_frame`]; handlers
+/// running under a [`reactor::Reactor`] should send frames marshalled into
+/// bytes, which are written atomically.
+///
+/// The transport can send keepalive frames through an active session which
+/// has not sent anything for a while (see [`FramedTransport::with_keepalive`]).
+/// Keepalives are written as regular frames: they get encoded by the session,
+/// are counted in the write buffer and are delivered to the remote peer as
+/// [`SessionEvent::Frame`] events, so the protocol must reserve a frame value
+/// which the remote handler recognizes and ignores.
+#[derive(Debug)]
+pub struct FramedTransport<S: NetSession, F: Frame> {
+    transport: NetTransport<S>,
+    marshaller: Marshaller,
+    frames: VecDeque<F>,
+    decode_error: Option<io::Error>,
+    keepalive: Option<(Duration, F)>,
+    last_keepalive: Instant,
+}
+
+impl<S: NetSession, F: Frame> Display for FramedTransport<S, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { Display::fmt(&self.transport, f) }
+}
+
+impl<S: NetSession, F: Frame> AsRawFd for FramedTransport<S, F> {
+    fn as_raw_fd(&self) -> RawFd { self.transport.as_raw_fd() }
+}
+
+impl<S: NetSession, F: Frame> From<NetTransport<S>> for FramedTransport<S, F>
+where F::Error: Sync + 'static
+{
+    fn from(transport: NetTransport<S>) -> Self { Self::new(transport) }
+}
+
+impl<S: NetSession, F: Frame> FramedTransport<S, F>
+where F::Error: Sync + 'static
+{
+    /// Constructs frame-aware transport around a [`NetTransport`].
+    pub fn new(transport: NetTransport<S>) -> Self {
+        Self {
+            transport,
+            marshaller: Marshaller::new(),
+            frames: empty!(),
+            decode_error: None,
+            keepalive: None,
+            last_keepalive: Instant::now(),
+        }
+    }
+
+    /// Makes the transport send `frame` once the active session has not sent
+    /// anything for the `interval`.
+    ///
+    /// The reactor doesn't poll resources on timers, so a [`reactor::Handler`]
+    /// must set a timer with [`reactor::Action::SetTimer`] for the interval;
+    /// this wakes the reactor up and makes it send the keepalive.
+    pub fn with_keepalive(mut self, interval: Duration, frame: F) -> Self {
+        self.keepalive = Some((interval, frame));
+        self
+    }
+
+    pub fn as_transport(&self) -> &NetTransport<S> { &self.transport }
+    pub fn as_transport_mut(&mut self) -> &mut NetTransport<S> { &mut self.transport }
+
+    /// Marshalls the frame and writes it to the transport atomically.
+    ///
+    /// # Errors
+    ///
+    /// With [`io::ErrorKind::InvalidInput`] if the frame can't be marshalled,
+    /// or if the underlying [`NetTransport`] can't accept the write.
+    pub fn write_frame(&mut self, frame: &F) -> io::Result<()> {
+        let mut buf = Vec::new();
+        frame.marshall(&mut buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.transport.write(&buf).map(|_| ())
+    }
+
+    fn decode(&mut self, data: Vec<u8>) -> Option<SessionEvent<S, F>> {
+        self.marshaller.write_all(&data).expect("in-memory write operation");
+        loop {
+            match self.marshaller.pop::<F>() {
+                Ok(Some(frame)) => self.frames.push_back(frame),
+                Ok(None) => break,
+                Err(err) => {
+                    #[cfg(feature = "log")]
+                    log::debug!(target: "transport", "Unable to decode frame from {self}: {err}");
+
+                    self.transport.state = TransportState::Terminated;
+                    self.decode_error = Some(io::Error::new(io::ErrorKind::InvalidData, err));
+                    break;
+                }
+            }
+        }
+        self.next_pending()
+    }
+
+    fn is_keepalive_due(&self) -> bool {
+        let last_write = self.transport.last_write().max(self.last_keepalive);
+        self.transport.is_active()
+            && matches!(self.keepalive, Some((interval, _)) if last_write.elapsed() >= interval)
+    }
+
+    #[allow(unused_variables)]
+    fn send_keepalive(&mut self) {
+        #[cfg(feature = "log")]
+        log::trace!(target: "transport", "Sending keepalive to {self}");
+
+        // Prevents repeated keepalives if the frame can't be written now
+        self.last_keepalive = Instant::now();
+        let mut buf = Vec::new();
+        if let Some((_, frame)) = &self.keepalive {
+            if let Err(err) = frame.marshall(&mut buf) {
+                #[cfg(feature = "log")]
+                log::error!(target: "transport", "Unable to marshall keepalive frame: {err}");
+                return;
+            }
+        }
+        if let Err(err) = self.transport.write_or_buf(&buf) {
+            #[cfg(feature = "log")]
+            log::warn!(target: "transport", "Unable to send keepalive to {self}: {err}");
+        }
+    }
+
+    fn next_pending(&mut self) -> Option<SessionEvent<S, F>> {
+        if let Some(frame) = self.frames.pop_front() {
+            return Some(SessionEvent::Frame(frame));
+        }
+        self.decode_error.take().map(SessionEvent::Terminated)
+    }
+}
+
+impl<S: NetSession, F: Frame> Resource for FramedTransport<S, F>
+where F::Error: Sync + 'static
+{
+    type Event = SessionEvent<S, F>;
+
+    fn interests(&self) -> IoType {
+        if !self.frames.is_empty() || self.decode_error.is_some() {
+            // Connected socket is always writable, so this makes the reactor
+            // call `handle_io` and deliver the pending frames.
+            return IoType::read_write();
+        }
+        if self.is_keepalive_due() {
+            // The same trick makes the reactor call `handle_io` to send the
+            // keepalive.
+            return IoType::read_write();
+        }
+        self.transport.interests()
+    }
+
+    fn handle_io(&mut self, io: Io) -> Option<Self::Event> {
+        if let Some(event) = self.next_pending() {
+            return Some(event);
+        }
+        if io == Io::Write && self.is_keepalive_due() {
+            self.send_keepalive();
+        }
+        match self.transport.handle_io(io)? {
+            SessionEvent::Data(data) => self.decode(data),
+            event => Some(event.with_frames()),
+        }
+    }
+}
+
+impl<S: NetSession, F: Frame> Write for FramedTransport<S, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.transport.write(buf) }
+
+    fn flush(&mut self) -> io::Result<()> { self.transport.flush() }
+}
+
+impl<S: NetSession, F: Frame> WriteAtomic for FramedTransport<S, F> {
+    fn is_ready_to_write(&self) -> bool { self.transport.is_ready_to_write() }
+
+    fn empty_write_buf(&mut self) -> io::Result<bool> { self.transport.empty_write_buf() }
+
+    fn write_or_buf(&mut self, buf: &[u8]) -> io::Result<()> { self.transport.write_or_buf(buf) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use cyphernet::encrypt::noise::{HandshakePattern, Keyset, NoiseState};
+    use cyphernet::{x25519, Sha256};
+
+    use super::*;
+    use crate::loopback::Loopback;
+    use crate::session::NoiseSession;
+
+    type Noise = NoiseSession<x25519::PrivateKey, Sha256, Loopback>;
+
+    fn noise(connection: Loopback, initiator: bool) -> Noise {
+        let state = NoiseState::initialize::<32>(
+            HandshakePattern::nn(),
+            initiator,
+            &[],
+            Keyset::noise_nn(),
+        );
+        NoiseSession::with(connection, state)
+    }
+
+    /// Calls `handle_io` for each of the transport interests, like the
+    /// reactor would do for a ready resource.
+    fn poll<S: NetSession>(transport: &mut NetTransport<S>) -> Vec<SessionEvent<S>> {
+        let interests = transport.interests();
+        let mut events = vec![];
+        for io in [Io::Read, Io::Write] {
+            let ready = match io {
+                Io::Read => interests.read,
+                Io::Write => interests.write,
+            };
+            if ready && transport.state() != TransportState::Terminated {
+                events.extend(transport.handle_io(io));
+            }
+        }
+        events
+    }
+
+    fn established<S: NetSession>(transport: &mut NetTransport<S>) {
+        assert!(matches!(transport.handle_io(Io::Write), Some(SessionEvent::Established(..))));
+        assert_eq!(transport.state(), TransportState::Active);
+    }
+
+    #[test]
+    fn connect() {
+        let (a, _b) = Loopback::pair().unwrap();
+        let mut transport = NetTransport::connect(a).unwrap();
+        assert_eq!(transport.state(), TransportState::Init);
+        assert!(transport.is_outbound());
+        assert_eq!(transport.interests(), IoType::write_only());
+        assert!(!transport.is_ready_to_write());
+        established(&mut transport);
+        assert!(transport.is_ready_to_write());
+    }
+
+    #[test]
+    fn with_session() {
+        let (a, _b) = Loopback::pair().unwrap();
+        let transport = NetTransport::with_session(a, Direction::Inbound).unwrap();
+        assert_eq!(transport.state(), TransportState::Active);
+        assert!(transport.is_inbound());
+        assert!(transport.is_ready_to_write());
+    }
+
+    #[test]
+    fn noise_handshake() {
+        let (a, mut b) = Loopback::pair().unwrap();
+        // Accepted connections are switched into non-blocking mode by `NetAccept`
+        b.set_nonblocking(true).unwrap();
+        let mut initiator = NetTransport::connect(noise(a, true)).unwrap();
+        let mut responder = NetTransport::accept(noise(b, false)).unwrap();
+        assert_eq!(responder.state(), TransportState::Handshake);
+
+        let mut established = 0;
+        for _ in 0..10 {
+            for event in poll(&mut initiator).into_iter().chain(poll(&mut responder)) {
+                match event {
+                    SessionEvent::Established(..) => established += 1,
+                    _ => panic!("unexpected event"),
+                }
+            }
+        }
+        assert_eq!(established, 2);
+        assert!(initiator.is_active());
+        assert!(responder.is_active());
+        assert_eq!(
+            initiator.expect_peer_id().state.handshake_hash,
+            responder.expect_peer_id().state.handshake_hash
+        );
+
+        initiator.write_or_buf(b"ping").unwrap();
+        match responder.handle_io(Io::Read) {
+            Some(SessionEvent::Data(data)) => assert_eq!(data, b"ping"),
+            _ => panic!("data event expected"),
+        }
+    }
+
+    #[test]
+    fn data() {
+        let (a, mut b) = Loopback::pair().unwrap();
+        let mut transport = NetTransport::with_session(a, Direction::Inbound).unwrap();
+
+        b.write_all(b"ping").unwrap();
+        match transport.handle_io(Io::Read) {
+            Some(SessionEvent::Data(data)) => assert_eq!(data, b"ping"),
+            _ => panic!("data event expected"),
+        }
+
+        transport.write_or_buf(b"pong").unwrap();
+        assert_eq!(transport.write_buf_len(), 0);
+        let mut buf = [0u8; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[test]
+    fn keepalive() {
+        type Ping = crate::frame::LengthPrefixed<Vec<u8>>;
+
+        let (a, b) = Loopback::pair().unwrap();
+        let transport = NetTransport::with_session(a, Direction::Outbound).unwrap();
+        let mut sender = FramedTransport::<_, Ping>::new(transport)
+            .with_keepalive(Duration::from_millis(10), Ping::new(vec![]));
+        let transport = NetTransport::with_session(b, Direction::Inbound).unwrap();
+        let mut receiver = FramedTransport::<_, Ping>::new(transport);
+
+        assert!(!sender.is_keepalive_due());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(sender.is_keepalive_due());
+        assert_eq!(sender.interests(), IoType::read_write());
+        assert!(sender.handle_io(Io::Write).is_none());
+        assert!(!sender.is_keepalive_due());
+
+        match receiver.handle_io(Io::Read) {
+            Some(SessionEvent::Frame(frame)) => assert!(frame.as_payload().is_empty()),
+            _ => panic!("keepalive frame expected"),
+        }
+    }
+
+    #[test]
+    fn watermarks() {
+        const LEN: usize = 4 * 1024 * 1024;
+
+        let (a, mut b) = Loopback::pair().unwrap();
+        let config = TransportConfig {
+            write_watermarks: Some(WriteWatermarks { high: 1024, low: 0 }),
+            ..default!()
+        };
+        let mut transport =
+            NetTransport::with_session_and_config(a, Direction::Outbound, config).unwrap();
+
+        // A single write exceeding the high watermark is buffered, but congests
+        // the transport
+        transport.write_or_buf(&vec![0xAA; LEN]).unwrap();
+        assert!(transport.is_congested());
+        assert!(transport.write_buf_len() > 1024);
+        assert!(transport.is_ready_to_write());
+        assert_eq!(transport.interests(), IoType::read_write());
+        assert!(matches!(transport.handle_io(Io::Write), Some(SessionEvent::Congested)));
+
+        // Further writes are rejected until the buffer is drained
+        let buffered = transport.write_buf_len();
+        let err = transport.write_atomic(b"more").unwrap_err();
+        match err {
+            WriteError::Io(err) => {
+                assert_eq!(err.kind(), io::ErrorKind::Other);
+                let err = err.into_inner().unwrap().downcast::<WriteBufferFull>().unwrap();
+                assert_eq!(*err, WriteBufferFull {
+                    buffered,
+                    high: 1024
+                });
+            }
+            WriteError::NotReady => panic!("transport must be ready"),
+        }
+        assert_eq!(transport.write_buf_len(), buffered);
+
+        let reader = std::thread::spawn(move || {
+            let mut buf = vec![0u8; LEN];
+            b.read_exact(&mut buf).unwrap();
+            (buf, b)
+        });
+        loop {
+            match transport.handle_io(Io::Write) {
+                None => std::thread::sleep(Duration::from_millis(1)),
+                Some(SessionEvent::Drained) => break,
+                Some(_) => panic!("unexpected event"),
+            }
+        }
+        assert!(!transport.is_congested());
+        assert_eq!(transport.write_buf_len(), 0);
+        let (data, _b) = reader.join().unwrap();
+        assert_eq!(data, vec![0xAA; LEN]);
+        transport.write_atomic(b"more").unwrap();
+    }
+
+    #[test]
+    fn remote_disconnect() {
+        let (a, b) = Loopback::pair().unwrap();
+        let mut transport = NetTransport::with_session(a, Direction::Inbound).unwrap();
+
+        drop(b);
+        match transport.handle_io(Io::Read) {
+            Some(SessionEvent::Terminated(err)) => {
+                assert_eq!(err.kind(), io::ErrorKind::ConnectionReset)
+            }
+            _ => panic!("termination event expected"),
+        }
+        assert_eq!(transport.state(), TransportState::Terminated);
+        assert_eq!(transport.interests(), IoType::none());
+    }
+
+    #[test]
+    fn graceful_close() {
+        let (a, mut b) = Loopback::pair().unwrap();
+        let mut transport = NetTransport::with_session(a, Direction::Inbound).unwrap();
+
+        transport.close();
+        assert_eq!(transport.state(), TransportState::Closing);
+        assert_eq!(transport.interests(), IoType::write_only());
+        match transport.handle_io(Io::Write) {
+            Some(SessionEvent::Terminated(err)) => {
+                assert!(err.get_ref().map(|err| err.is::<GracefulClose>()).unwrap_or_default())
+            }
+            _ => panic!("termination event expected"),
+        }
+        assert_eq!(transport.state(), TransportState::Terminated);
+        assert_eq!(b.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let (a, _b) = Loopback::pair().unwrap();
+        let config = TransportConfig {
+            handshake_timeout: Some(Duration::from_millis(10)),
+            ..default!()
+        };
+        let mut transport = NetTransport::connect_with_config(a, config).unwrap();
+        assert_eq!(transport.interests(), IoType::write_only());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(transport.state(), TransportState::Init);
+        assert_eq!(transport.interests(), IoType::read_write());
+        assert!(matches!(transport.handle_io(Io::Write), Some(SessionEvent::HandshakeTimeout)));
+        assert_eq!(transport.state(), TransportState::Terminated);
+    }
+
+    #[test]
+    fn closing_writes() {
+        let (a, mut b) = Loopback::pair().unwrap();
+        let mut transport = NetTransport::with_session(a, Direction::Inbound).unwrap();
+        transport.write_or_buf(b"ping").unwrap();
+
+        transport.close();
+        assert!(transport.close_deadline().is_some());
+        assert!(transport.is_ready_to_write());
+        transport.write_atomic(b"pong").unwrap();
+        assert!(matches!(transport.handle_io(Io::Write), Some(SessionEvent::Terminated(_))));
+
+        let mut buf = vec![];
+        b.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"ping");
+    }
+
+    #[test]
+    fn close_timeout() {
+        const LEN: usize = 4 * 1024 * 1024;
+
+        let (a, _b) = Loopback::pair().unwrap();
+        let config = TransportConfig {
+            write_timeout: Duration::from_millis(10),
+            ..default!()
+        };
+        let mut transport =
+            NetTransport::with_session_and_config(a, Direction::Inbound, config).unwrap();
+        transport.write_or_buf(&vec![0u8; LEN]).unwrap();
+        assert!(transport.write_buf_len() > 0);
+
+        transport.close();
+        assert_eq!(transport.interests(), IoType::write_only());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(transport.interests(), IoType::read_write());
+        match transport.handle_io(Io::Read) {
+            Some(SessionEvent::Terminated(err)) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+            _ => panic!("termination event expected"),
+        }
+        assert_eq!(transport.state(), TransportState::Terminated);
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let (a, _b) = Loopback::pair().unwrap();
+        let config = TransportConfig {
+            handshake_timeout: Some(Duration::ZERO),
+            ..default!()
+        };
+        let mut transport = NetTransport::connect_with_config(a, config).unwrap();
+        assert!(transport.is_handshake_expired());
+        assert!(matches!(transport.handle_io(Io::Write), Some(SessionEvent::HandshakeTimeout)));
+        assert_eq!(transport.state(), TransportState::Terminated);
+    }
+
+    fn is_readable(fd: RawFd) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, 100) == 1 }
+    }
+
+    #[test]
+    fn accept_batch() {
+        use std::net::{SocketAddr, TcpStream};
+
+        let accept =
+            NetAccept::<TcpStream>::bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap()).unwrap();
+        assert_eq!(accept.batch_size(), 1);
+        let listener_fd = accept.as_raw_fd();
+        let mut accept = accept.with_accept_batch(2).unwrap();
+        assert!(NetAccept::<TcpStream>::bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap())
+            .unwrap()
+            .with_accept_batch(0)
+            .is_err());
+        if !AcceptWaker::IS_SUPPORTED {
+            assert_eq!(accept.batch_size(), 1);
+            return;
+        }
+        assert_eq!(accept.batch_size(), 2);
+        assert_ne!(accept.as_raw_fd(), listener_fd);
+
+        let addr = accept.local_addr();
+        let clients = (0..3).map(|_| TcpStream::connect(addr).unwrap()).collect::<Vec<_>>();
+        let mut accepted = vec![];
+        while accepted.len() < clients.len() {
+            assert!(is_readable(accept.as_raw_fd()));
+            match accept.handle_io(Io::Read) {
+                Some(ListenerEvent::Accepted(connection)) => accepted.push(connection),
+                _ => panic!("accepted event expected"),
+            }
+        }
+        // All the connections were accepted in two batches
+        assert!(accept.queue.is_empty());
+        assert!(!is_readable(accept.as_raw_fd()));
+        for (connection, client) in accepted.iter().zip(&clients) {
+            assert_eq!(connection.peer_addr().unwrap(), client.local_addr().unwrap());
+        }
     }
 }
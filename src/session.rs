@@ -32,23 +32,28 @@ use cyphernet::addr::{HostName, InetHost, NetAddr};
 use cyphernet::auth::eidolon::EidolonState;
 use cyphernet::encrypt::noise::NoiseState;
 #[cfg(feature = "eidolon")]
-use cyphernet::encrypt::noise::{HandshakePattern, Keyset};
+use cyphernet::encrypt::noise::{HandshakePattern, InitiatorPattern, Keyset, OneWayPattern};
 use cyphernet::proxy::socks5;
 #[cfg(feature = "eidolon")]
-use cyphernet::{x25519, Cert, Digest, EcSign};
+use cyphernet::{x25519, Cert, Digest, EcSign, EcSk};
 
+use crate::proxy::Socks5Client;
 #[cfg(feature = "eidolon")]
-use crate::Direction;
+use crate::proxy::{ProxyClient, ProxyCredentials, ProxyProtocol, ProxySession};
+#[cfg(feature = "eidolon")]
+use crate::{AllowAll, Direction, PeerAuthorizer};
 use crate::{NetConnection, NetReader, NetStream, NetWriter, SplitIo, SplitIoError};
 
 #[cfg(feature = "eidolon")]
 pub type EidolonSession<I, S> = NetProtocol<EidolonRuntime<I>, S>;
 pub type NoiseSession<E, D, S> = NetProtocol<NoiseState<E, D>, S>;
 pub type Socks5Session<S> = NetProtocol<socks5::Socks5, S>;
+/// SOCKS5 client session supporting username/password authentication.
+pub type Socks5AuthSession<S> = NetProtocol<Socks5Client, S>;
 
 #[cfg(feature = "eidolon")]
 pub type CypherSession<I, D> =
-    EidolonSession<I, NoiseSession<x25519::PrivateKey, D, Socks5Session<TcpStream>>>;
+    EidolonSession<I, NoiseSession<x25519::PrivateKey, D, ProxySession<TcpStream>>>;
 
 #[cfg(feature = "eidolon")]
 pub type EidolonReader<S> = NetReader<S>;
@@ -56,13 +61,28 @@ pub type EidolonReader<S> = NetReader<S>;
 pub type EidolonWriter<I, S> = NetWriter<EidolonRuntime<I>, S>;
 #[cfg(feature = "eidolon")]
 pub type CypherReader<D> =
-    EidolonReader<NoiseSession<x25519::PrivateKey, D, Socks5Session<TcpStream>>>;
+    EidolonReader<NoiseSession<x25519::PrivateKey, D, ProxySession<TcpStream>>>;
 #[cfg(feature = "eidolon")]
 pub type CypherWriter<I, D> =
-    EidolonWriter<I, NoiseSession<x25519::PrivateKey, D, Socks5Session<TcpStream>>>;
+    EidolonWriter<I, NoiseSession<x25519::PrivateKey, D, ProxySession<TcpStream>>>;
+
+/// Converts list of allowed ids taken by [`CypherSession`] and
+/// [`EidolonRuntime`] constructors and by [`CypherSessionBuilder::allowed_ids`]
+/// into an authorizer. For backward compatibility, an empty list allows any
+/// remote peer, unlike [`PeerAuthorizer`] implementation for `Vec`.
+#[cfg(feature = "eidolon")]
+fn legacy_allowlist<Pk: Eq + Send + 'static>(allowed_ids: Vec<Pk>) -> Box<dyn PeerAuthorizer<Pk>> {
+    if allowed_ids.is_empty() {
+        Box::new(AllowAll)
+    } else {
+        Box::new(allowed_ids)
+    }
+}
 
 #[cfg(feature = "eidolon")]
-impl<I: EcSign, D: Digest> CypherSession<I, D> {
+impl<I: EcSign, D: Digest> CypherSession<I, D>
+where I::Pk: Sync + 'static
+{
     #[cfg(feature = "reactor")]
     pub fn connect_nonblocking<const HASHLEN: usize>(
         remote_addr: NetAddr<HostName>,
@@ -73,20 +93,13 @@ impl<I: EcSign, D: Digest> CypherSession<I, D> {
         force_proxy: bool,
         timeout: Duration,
     ) -> io::Result<Self> {
-        let connection = if force_proxy {
-            TcpStream::connect_nonblocking(proxy_addr, timeout)?
-        } else {
-            TcpStream::connect_nonblocking(remote_addr.connection_addr(proxy_addr), timeout)?
-        };
-        Ok(Self::with_config::<HASHLEN>(
-            remote_addr,
-            connection,
-            Direction::Outbound,
-            cert,
-            allowed_ids,
-            signer,
-            force_proxy,
-        ))
+        CypherSessionBuilder::new(cert, signer)
+            .hash_len::<HASHLEN>()
+            .authorizer(legacy_allowlist(allowed_ids))
+            .proxy(proxy_addr)
+            .force_proxy(force_proxy)
+            .timeout(timeout)
+            .connect_nonblocking(remote_addr)
     }
 
     #[cfg(feature = "reactor")]
@@ -99,23 +112,13 @@ impl<I: EcSign, D: Digest> CypherSession<I, D> {
         proxy_addr: NetAddr<InetHost>,
         force_proxy: bool,
     ) -> io::Result<Self> {
-        let connection = if force_proxy {
-            TcpStream::connect_reusable_nonblocking(local_addr, proxy_addr)?
-        } else {
-            TcpStream::connect_reusable_nonblocking(
-                local_addr,
-                remote_addr.connection_addr(proxy_addr),
-            )?
-        };
-        Ok(Self::with_config::<HASHLEN>(
-            remote_addr,
-            connection,
-            Direction::Outbound,
-            cert,
-            allowed_ids,
-            signer,
-            force_proxy,
-        ))
+        CypherSessionBuilder::new(cert, signer)
+            .hash_len::<HASHLEN>()
+            .authorizer(legacy_allowlist(allowed_ids))
+            .proxy(proxy_addr)
+            .force_proxy(force_proxy)
+            .local_addr(local_addr)
+            .connect_nonblocking(remote_addr)
     }
 
     pub fn connect_blocking<const HASHLEN: usize>(
@@ -127,22 +130,13 @@ impl<I: EcSign, D: Digest> CypherSession<I, D> {
         force_proxy: bool,
         timeout: Duration,
     ) -> io::Result<Self> {
-        let connection = if force_proxy {
-            TcpStream::connect_blocking(proxy_addr, timeout)?
-        } else {
-            TcpStream::connect_blocking(remote_addr.connection_addr(proxy_addr), timeout)?
-        };
-        let mut session = Self::with_config::<HASHLEN>(
-            remote_addr,
-            connection,
-            Direction::Outbound,
-            cert,
-            allowed_ids,
-            signer,
-            force_proxy,
-        );
-        session.run_handshake()?;
-        Ok(session)
+        CypherSessionBuilder::new(cert, signer)
+            .hash_len::<HASHLEN>()
+            .authorizer(legacy_allowlist(allowed_ids))
+            .proxy(proxy_addr)
+            .force_proxy(force_proxy)
+            .timeout(timeout)
+            .connect_blocking(remote_addr)
     }
 
     pub fn accept<const HASHLEN: usize>(
@@ -151,41 +145,320 @@ impl<I: EcSign, D: Digest> CypherSession<I, D> {
         allowed_ids: Vec<I::Pk>,
         signer: I,
     ) -> io::Result<Self> {
-        Ok(Self::with_config::<HASHLEN>(
-            connection.remote_addr()?.into(),
-            connection,
-            Direction::Inbound,
+        CypherSessionBuilder::new(cert, signer)
+            .hash_len::<HASHLEN>()
+            .authorizer(legacy_allowlist(allowed_ids))
+            .accept(connection)
+    }
+}
+
+/// Noise handshake pattern used by [`CypherSession`], together with the static
+/// keys it requires.
+///
+/// Patterns other than [`NoisePattern::Nn`] bind the peer identities at the
+/// Noise layer: the remote static key becomes available as
+/// [`NoiseArtifact::remote_static_key`] once the handshake is complete.
+///
+/// The IK pattern is not provided, since the underlying Noise implementation
+/// uses wrong pre-messages for it, making the responder unable to complete the
+/// handshake.
+#[cfg(feature = "eidolon")]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum NoisePattern {
+    /// No static keys are used; the transport remains unauthenticated until
+    /// the Eidolon handshake completes.
+    #[default]
+    Nn,
+
+    /// The initiator knows the responder static key in advance and transmits
+    /// its own static key in the last handshake act.
+    ///
+    /// The remote key must be given when connecting and is ignored when
+    /// accepting.
+    Xk {
+        local: x25519::PrivateKey,
+        remote: Option<x25519::PublicKey>,
+    },
+
+    /// Both peers know each other static keys in advance.
+    Kk {
+        local: x25519::PrivateKey,
+        remote: x25519::PublicKey,
+    },
+}
+
+#[cfg(feature = "eidolon")]
+impl NoisePattern {
+    /// Returns the Noise handshake pattern.
+    pub fn handshake_pattern(&self) -> HandshakePattern {
+        match self {
+            NoisePattern::Nn => HandshakePattern::nn(),
+            NoisePattern::Xk { .. } => HandshakePattern {
+                initiator: InitiatorPattern::Xmitted,
+                responder: OneWayPattern::Known,
+            },
+            NoisePattern::Kk { .. } => HandshakePattern {
+                initiator: InitiatorPattern::Known,
+                responder: OneWayPattern::Known,
+            },
+        }
+    }
+
+    /// Constructs the Noise keyset for the given direction of the connection.
+    ///
+    /// # Errors
+    ///
+    /// Errors with [`io::ErrorKind::InvalidInput`] if the remote static key
+    /// required by the pattern is not known.
+    pub fn keyset(&self, direction: Direction) -> io::Result<Keyset<x25519::PrivateKey>> {
+        let (local, remote) = match self {
+            NoisePattern::Nn => return Ok(Keyset::noise_nn()),
+            NoisePattern::Xk { local, .. } if direction == Direction::Inbound => (local, None),
+            NoisePattern::Xk {
+                local,
+                remote: Some(remote),
+            } => (local, Some(remote)),
+            NoisePattern::Xk { remote: None, .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Noise XK pattern requires remote static key for outgoing connections",
+                ))
+            }
+            NoisePattern::Kk { local, remote } => (local, Some(remote)),
+        };
+        Ok(Keyset {
+            e: x25519::PrivateKey::generate_keypair().0,
+            s: Some(local.clone()),
+            re: None,
+            rs: remote.copied(),
+        })
+    }
+}
+
+/// Default timeout for establishing connections by [`CypherSessionBuilder`].
+#[cfg(feature = "eidolon")]
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builder constructing [`CypherSession`]s.
+///
+/// The `HASHLEN` must match the output length of the digest type used by the
+/// constructed session; it defaults to 32 bytes and can be changed with
+/// [`CypherSessionBuilder::hash_len`].
+#[cfg(feature = "eidolon")]
+pub struct CypherSessionBuilder<I: EcSign, const HASHLEN: usize = 32> {
+    cert: Cert<I::Sig>,
+    signer: I,
+    authorizer: Box<dyn PeerAuthorizer<I::Pk>>,
+    proxy_addr: Option<NetAddr<InetHost>>,
+    proxy_protocol: ProxyProtocol,
+    proxy_credentials: Option<ProxyCredentials>,
+    force_proxy: bool,
+    timeout: Duration,
+    local_addr: Option<NetAddr<InetHost>>,
+    noise: NoisePattern,
+}
+
+#[cfg(feature = "eidolon")]
+impl<I: EcSign> CypherSessionBuilder<I> {
+    /// Constructs builder using the local identity certificate and a signer
+    /// for it.
+    pub fn new(cert: Cert<I::Sig>, signer: I) -> Self {
+        CypherSessionBuilder {
             cert,
-            allowed_ids,
             signer,
-            false,
-        ))
+            authorizer: Box::new(AllowAll),
+            proxy_addr: None,
+            proxy_protocol: ProxyProtocol::Socks5,
+            proxy_credentials: None,
+            force_proxy: false,
+            timeout: CONNECT_TIMEOUT,
+            local_addr: None,
+            noise: NoisePattern::Nn,
+        }
+    }
+}
+
+#[cfg(feature = "eidolon")]
+impl<I: EcSign, const HASHLEN: usize> CypherSessionBuilder<I, HASHLEN>
+where I::Pk: Sync + 'static
+{
+    /// Sets the list of remote ids allowed to authenticate. Like with the
+    /// [`CypherSession`] constructors, an empty list allows any remote id; use
+    /// [`CypherSessionBuilder::authorizer`] for other policies.
+    pub fn allowed_ids(self, allowed_ids: impl IntoIterator<Item = I::Pk>) -> Self {
+        self.authorizer(legacy_allowlist(allowed_ids.into_iter().collect()))
+    }
+
+    /// Sets the policy authorizing remote ids; defaults to [`AllowAll`].
+    pub fn authorizer(mut self, authorizer: impl PeerAuthorizer<I::Pk> + 'static) -> Self {
+        self.authorizer = Box::new(authorizer);
+        self
+    }
+
+    /// Sets proxy address, which is used for connecting to the remote peers
+    /// requiring proxy (like Tor onion services), or to all peers if
+    /// [`CypherSessionBuilder::force_proxy`] is set. The proxy protocol is set
+    /// with [`CypherSessionBuilder::proxy_protocol`].
+    pub fn proxy(mut self, proxy_addr: NetAddr<InetHost>) -> Self {
+        self.proxy_addr = Some(proxy_addr);
+        self
+    }
+
+    /// Sets protocol used for connecting via proxy; defaults to
+    /// [`ProxyProtocol::Socks5`].
+    pub fn proxy_protocol(mut self, protocol: ProxyProtocol) -> Self {
+        self.proxy_protocol = protocol;
+        self
+    }
+
+    /// Sets username and password for authenticating with the proxy. Since Tor
+    /// isolates circuits by SOCKS credentials, this allows to use a dedicated
+    /// circuit per session.
+    pub fn proxy_credentials(mut self, credentials: ProxyCredentials) -> Self {
+        self.proxy_credentials = Some(credentials);
+        self
+    }
+
+    /// Requires all outgoing connections to go through the proxy.
+    pub fn force_proxy(mut self, force_proxy: bool) -> Self {
+        self.force_proxy = force_proxy;
+        self
+    }
+
+    /// Sets connection timeout; defaults to [`CONNECT_TIMEOUT`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the local address to bind outgoing connections to, using
+    /// `SO_REUSEADDR`/`SO_REUSEPORT`. Applies only to non-blocking connections.
+    pub fn local_addr(mut self, local_addr: NetAddr<InetHost>) -> Self {
+        self.local_addr = Some(local_addr);
+        self
+    }
+
+    /// Sets the Noise handshake pattern; defaults to [`NoisePattern::Nn`].
+    pub fn noise(mut self, noise: NoisePattern) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    /// Changes the handshake hash length, which must match the output length
+    /// of the digest used by the session.
+    pub fn hash_len<const LEN: usize>(self) -> CypherSessionBuilder<I, LEN> {
+        CypherSessionBuilder {
+            cert: self.cert,
+            signer: self.signer,
+            authorizer: self.authorizer,
+            proxy_addr: self.proxy_addr,
+            proxy_protocol: self.proxy_protocol,
+            proxy_credentials: self.proxy_credentials,
+            force_proxy: self.force_proxy,
+            timeout: self.timeout,
+            local_addr: self.local_addr,
+            noise: self.noise,
+        }
     }
 
-    fn with_config<const HASHLEN: usize>(
+    /// Creates an outgoing session with a non-blocking connection to the
+    /// remote peer. The session handshake is not performed.
+    #[cfg(feature = "reactor")]
+    pub fn connect_nonblocking<D: Digest>(
+        self,
+        remote_addr: NetAddr<HostName>,
+    ) -> io::Result<CypherSession<I, D>> {
+        let addr = self.connection_addr(&remote_addr)?;
+        let keyset = self.noise.keyset(Direction::Outbound)?;
+        let connection = match self.local_addr.clone() {
+            Some(local_addr) => TcpStream::connect_reusable_nonblocking(local_addr, addr)?,
+            None => TcpStream::connect_nonblocking(addr, self.timeout)?,
+        };
+        Ok(self.build(remote_addr, connection, Direction::Outbound, keyset))
+    }
+
+    /// Creates an outgoing session with a blocking connection to the remote
+    /// peer and performs the session handshake.
+    ///
+    /// # Errors
+    ///
+    /// Errors with [`io::ErrorKind::Unsupported`] if the local address was set
+    /// for the builder.
+    pub fn connect_blocking<D: Digest>(
+        self,
+        remote_addr: NetAddr<HostName>,
+    ) -> io::Result<CypherSession<I, D>> {
+        if self.local_addr.is_some() {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        let addr = self.connection_addr(&remote_addr)?;
+        let keyset = self.noise.keyset(Direction::Outbound)?;
+        let connection = TcpStream::connect_blocking(addr, self.timeout)?;
+        let mut session = self.build(remote_addr, connection, Direction::Outbound, keyset);
+        session.run_handshake()?;
+        Ok(session)
+    }
+
+    /// Creates an incoming session for the accepted connection. The session
+    /// handshake is not performed.
+    pub fn accept<D: Digest>(self, connection: TcpStream) -> io::Result<CypherSession<I, D>> {
+        let remote_addr = connection.remote_addr()?.into();
+        let keyset = self.noise.keyset(Direction::Inbound)?;
+        Ok(self.build(remote_addr, connection, Direction::Inbound, keyset))
+    }
+
+    fn connection_addr(&self, remote_addr: &NetAddr<HostName>) -> io::Result<NetAddr<InetHost>> {
+        match &self.proxy_addr {
+            Some(proxy_addr) if self.force_proxy => Ok(proxy_addr.clone()),
+            Some(proxy_addr) => Ok(remote_addr.connection_addr(proxy_addr.clone())),
+            None if self.force_proxy => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "proxy is forced, but no proxy address is given",
+            )),
+            None => match &remote_addr.host {
+                HostName::Ip(ip) => Ok(NetAddr::new(InetHost::Ip(*ip), remote_addr.port)),
+                HostName::Dns(dns) => {
+                    Ok(NetAddr::new(InetHost::Dns(dns.clone()), remote_addr.port))
+                }
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "remote address {remote_addr} requires proxy, but no proxy address is \
+                         given"
+                    ),
+                )),
+            },
+        }
+    }
+
+    fn build<D: Digest>(
+        self,
         remote_addr: NetAddr<HostName>,
         connection: TcpStream,
         direction: Direction,
-        cert: Cert<I::Sig>,
-        allowed_ids: Vec<I::Pk>,
-        signer: I,
-        force_proxy: bool,
-    ) -> Self {
-        let socks5 = socks5::Socks5::with(remote_addr, force_proxy);
-        let proxy = Socks5Session::with(connection, socks5);
+        keyset: Keyset<x25519::PrivateKey>,
+    ) -> CypherSession<I, D> {
+        let proxy = match direction {
+            Direction::Outbound if self.proxy_addr.is_some() => ProxyClient::with(
+                self.proxy_protocol,
+                remote_addr,
+                self.force_proxy,
+                self.proxy_credentials,
+            ),
+            Direction::Outbound | Direction::Inbound => ProxyClient::direct(remote_addr),
+        };
+        let proxy = ProxySession::with(connection, proxy);
 
         let noise = NoiseState::initialize::<HASHLEN>(
-            HandshakePattern::nn(),
+            self.noise.handshake_pattern(),
             direction.is_outbound(),
             &[],
-            Keyset::noise_nn(),
+            keyset,
         );
 
         let encoding = NoiseSession::with(proxy, noise);
-        let eidolon = match direction {
-            Direction::Inbound => EidolonRuntime::responder(signer, cert, allowed_ids),
-            Direction::Outbound => EidolonRuntime::initiator(signer, cert, allowed_ids),
-        };
+        let eidolon =
+            EidolonRuntime::with_boxed(self.signer, self.cert, self.authorizer, direction);
         EidolonSession::with(encoding, eidolon)
     }
 }
@@ -212,12 +485,86 @@ pub trait NetSession: NetStream + SplitIo {
     fn disconnect(self) -> io::Result<()>;
 }
 
-#[derive(Clone, Debug, Display, Error)]
-#[display("handshake has failed due to {0}")]
-pub struct HandshakeError(String);
+/// Classification of session handshake failures.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum HandshakeErrorKind {
+    /// protocol violation
+    ProtocolViolation,
+
+    /// authentication rejected
+    AuthRejected,
+
+    /// proxy failure
+    ProxyFailure,
+
+    /// timeout
+    Timeout,
+}
+
+/// Failure of a session handshake.
+///
+/// The error is returned inside [`io::Error`] (with [`io::ErrorKind::TimedOut`]
+/// kind for timeouts and [`io::ErrorKind::ConnectionAborted`] otherwise) and
+/// can be recovered with [`HandshakeError::from_io`].
+#[derive(Debug, Display)]
+#[display("{layer} handshake has failed due to {source}")]
+pub struct HandshakeError {
+    /// [`NetStateMachine::NAME`] of the failed protocol layer.
+    pub layer: &'static str,
+    /// Classification of the failure.
+    pub kind: HandshakeErrorKind,
+    /// Error of the failed protocol layer: [`io::Error`] for timeouts and
+    /// [`LayerError`] otherwise.
+    pub source: Box<dyn error::Error + Send + Sync>,
+}
+
+/// Error of a failed protocol layer, which is erased to its message, since the
+/// errors of [`NetStateMachine`]s are not required to be thread-safe.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display("{0}")]
+pub struct LayerError(pub String);
+
+impl error::Error for HandshakeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> { Some(self.source.as_ref()) }
+}
 
 impl HandshakeError {
-    fn with(err: impl error::Error) -> Self { HandshakeError(err.to_string()) }
+    /// Constructs error from the failure of the protocol state machine `M`.
+    pub fn with<M: NetStateMachine>(err: M::Error) -> Self {
+        HandshakeError {
+            layer: M::NAME,
+            kind: M::error_kind(&err),
+            source: Box::new(LayerError(err.to_string())),
+        }
+    }
+
+    /// Constructs timeout error of the protocol layer `M`.
+    pub fn timeout<M: NetStateMachine>(err: io::Error) -> Self {
+        HandshakeError {
+            layer: M::NAME,
+            kind: HandshakeErrorKind::Timeout,
+            source: Box::new(err),
+        }
+    }
+
+    /// Extracts the handshake error from an I/O error, if it was caused by a
+    /// handshake failure.
+    pub fn from_io(err: &io::Error) -> Option<&Self> { err.get_ref()?.downcast_ref() }
+
+    /// Returns the error of the failed protocol layer, if it has the type `E`
+    /// (see [`HandshakeError::source`]).
+    pub fn source_as<E: error::Error + 'static>(&self) -> Option<&E> { self.source.downcast_ref() }
+}
+
+impl From<HandshakeError> for io::Error {
+    fn from(err: HandshakeError) -> Self {
+        let kind = match err.kind {
+            HandshakeErrorKind::Timeout => io::ErrorKind::TimedOut,
+            _ => io::ErrorKind::ConnectionAborted,
+        };
+        io::Error::new(kind, err)
+    }
 }
 
 pub trait NetStateMachine: Sized + Send {
@@ -232,16 +579,34 @@ pub trait NetStateMachine: Sized + Send {
     fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Self::Error>;
     fn artifact(&self) -> Option<Self::Artifact>;
 
+    /// Classifies handshake failure; defaults to
+    /// [`HandshakeErrorKind::ProtocolViolation`].
+    #[allow(unused_variables)]
+    fn error_kind(err: &Self::Error) -> HandshakeErrorKind { HandshakeErrorKind::ProtocolViolation }
+
     // Blocking
-    #[allow(clippy::read_zero_byte_vec, unused_variables)]
+    #[allow(unused_variables)]
     fn run_handshake(&mut self, stream: &mut impl NetStream) -> io::Result<()> {
-        let mut input = vec![];
         while !self.is_complete() {
+            // Responders (and initiators in the middle of a handshake) have to
+            // receive the remote act before they can advance
+            let mut input = vec![0u8; self.next_read_len()];
+            if !input.is_empty() {
+                stream.read_exact(&mut input).map_err(|err| match err.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        HandshakeError::timeout::<Self>(err).into()
+                    }
+                    _ => err,
+                })?;
+
+                #[cfg(feature = "log")]
+                log::trace!(target: Self::NAME, "Receiving handshake act {input:02x?}");
+            }
             let act = self.advance(&input).map_err(|err| {
                 #[cfg(feature = "log")]
                 log::error!(target: Self::NAME, "Handshake failure: {err}");
 
-                io::Error::new(io::ErrorKind::ConnectionAborted, HandshakeError::with(err))
+                io::Error::from(HandshakeError::with::<Self>(err))
             })?;
             if !act.is_empty() {
                 #[cfg(feature = "log")]
@@ -249,13 +614,6 @@ pub trait NetStateMachine: Sized + Send {
 
                 stream.write_all(&act)?;
             }
-            if !self.is_complete() {
-                input = vec![0u8; self.next_read_len()];
-                stream.read_exact(&mut input)?;
-
-                #[cfg(feature = "log")]
-                log::trace!(target: Self::NAME, "Receiving handshake act {input:02x?}");
-            }
         }
         #[cfg(feature = "log")]
         log::debug!(target: Self::NAME, "Handshake protocol {} successfully completed", Self::NAME);
@@ -359,7 +717,7 @@ where S::Artifact: IntoInit<M::Init>
                 #[cfg(feature = "log")]
                 log::error!(target: M::NAME, "Handshake failure: {err}");
 
-                io::Error::new(io::ErrorKind::ConnectionAborted, HandshakeError::with(err))
+                io::Error::from(HandshakeError::with::<M>(err))
             })?;
 
             if !output.is_empty() {
@@ -399,7 +757,7 @@ where S::Artifact: IntoInit<M::Init>
                 #[cfg(feature = "log")]
                 log::error!(target: M::NAME, "Handshake failure: {err}");
 
-                io::Error::new(io::ErrorKind::ConnectionAborted, HandshakeError::with(err))
+                io::Error::from(HandshakeError::with::<M>(err))
             })?;
 
             if !act.is_empty() {
@@ -499,8 +857,10 @@ where S::Artifact: IntoInit<M::Init>
 
 mod imp_std {
     use std::net::{Shutdown, SocketAddr, TcpStream};
+    use std::os::unix::net::UnixStream;
 
     use super::*;
+    use crate::UnixAddr;
 
     impl NetSession for TcpStream {
         type Inner = Self;
@@ -517,6 +877,22 @@ mod imp_std {
 
         fn disconnect(self) -> io::Result<()> { self.shutdown(Shutdown::Both) }
     }
+
+    impl NetSession for UnixStream {
+        type Inner = Self;
+        type Connection = Self;
+        type Artifact = UnixAddr;
+
+        fn run_handshake(&mut self) -> io::Result<()> { Ok(()) }
+
+        fn artifact(&self) -> Option<Self::Artifact> { self.peer_addr().ok().map(UnixAddr::from) }
+
+        fn as_connection(&self) -> &Self::Connection { self }
+
+        fn as_connection_mut(&mut self) -> &mut Self::Connection { self }
+
+        fn disconnect(self) -> io::Result<()> { self.shutdown(Shutdown::Both) }
+    }
 }
 
 #[cfg(feature = "socket2")]
@@ -553,24 +929,75 @@ mod imp_eidolon {
     use cyphernet::{Cert, CertFormat, Digest, EcSign, Ecdh};
 
     use super::*;
+    use crate::PeerAuthorizer;
 
     pub struct EidolonRuntime<S: EcSign> {
         state: EidolonState<S::Sig>,
         signer: S,
+        authorizer: Box<dyn PeerAuthorizer<S::Pk>>,
+        authorized: bool,
     }
 
     impl<S: EcSign> EidolonRuntime<S> {
-        pub fn initiator(signer: S, cert: Cert<S::Sig>, allowed_ids: Vec<S::Pk>) -> Self {
+        /// Constructs the initiator of the Eidolon handshake, which allows only
+        /// the remote ids from the list; an empty list allows any remote id.
+        pub fn initiator(signer: S, cert: Cert<S::Sig>, allowed_ids: Vec<S::Pk>) -> Self
+        where S::Pk: Send + 'static {
+            Self::with_boxed(signer, cert, legacy_allowlist(allowed_ids), Direction::Outbound)
+        }
+
+        /// Constructs the responder of the Eidolon handshake, which allows only
+        /// the remote ids from the list; an empty list allows any remote id.
+        pub fn responder(signer: S, cert: Cert<S::Sig>, allowed_ids: Vec<S::Pk>) -> Self
+        where S::Pk: Send + 'static {
+            Self::with_boxed(signer, cert, legacy_allowlist(allowed_ids), Direction::Inbound)
+        }
+
+        /// Constructs the initiator of the Eidolon handshake. The authorizer is
+        /// queried for the remote id once it gets authenticated.
+        pub fn initiator_with_authorizer(
+            signer: S,
+            cert: Cert<S::Sig>,
+            authorizer: impl PeerAuthorizer<S::Pk> + 'static,
+        ) -> Self {
+            Self {
+                state: EidolonState::initiator(cert, vec![]),
+                signer,
+                authorizer: Box::new(authorizer),
+                authorized: false,
+            }
+        }
+
+        pub(crate) fn with_boxed(
+            signer: S,
+            cert: Cert<S::Sig>,
+            authorizer: Box<dyn PeerAuthorizer<S::Pk>>,
+            direction: Direction,
+        ) -> Self {
+            let state = match direction {
+                Direction::Inbound => EidolonState::responder(cert, vec![]),
+                Direction::Outbound => EidolonState::initiator(cert, vec![]),
+            };
             Self {
-                state: EidolonState::initiator(cert, allowed_ids),
+                state,
                 signer,
+                authorizer,
+                authorized: false,
             }
         }
 
-        pub fn responder(signer: S, cert: Cert<S::Sig>, allowed_ids: Vec<S::Pk>) -> Self {
+        /// Constructs the responder of the Eidolon handshake. The authorizer is
+        /// queried for the remote id once it gets authenticated.
+        pub fn responder_with_authorizer(
+            signer: S,
+            cert: Cert<S::Sig>,
+            authorizer: impl PeerAuthorizer<S::Pk> + 'static,
+        ) -> Self {
             Self {
-                state: EidolonState::responder(cert, allowed_ids),
+                state: EidolonState::responder(cert, vec![]),
                 signer,
+                authorizer: Box::new(authorizer),
+                authorized: false,
             }
         }
     }
@@ -586,7 +1013,9 @@ mod imp_eidolon {
         }
     }
 
-    impl<S: EcSign> NetStateMachine for EidolonRuntime<S> {
+    impl<S: EcSign> NetStateMachine for EidolonRuntime<S>
+    where S::Pk: Sync + 'static
+    {
         const NAME: &'static str = "eidolon";
         type Init = Vec<u8>;
         type Artifact = Cert<S::Sig>;
@@ -597,10 +1026,34 @@ mod imp_eidolon {
         fn next_read_len(&self) -> usize { self.state.next_read_len() }
 
         fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Self::Error> {
-            self.state.advance(input, &self.signer)
+            let output = self.state.advance(input, &self.signer)?;
+            if let Some(cert) = self.state.remote_cert() {
+                // The responder output contains its credentials, which must not be sent to an
+                // unauthorized peer; thus we check before returning it.
+                if !self.authorizer.is_authorized(&cert.pk) {
+                    #[cfg(feature = "log")]
+                    log::warn!(target: "eidolon", "Remote id {:?} is not authorized", cert.pk);
+                    return Err(eidolon::Error::Unauthorized(cert.pk.clone()));
+                }
+                self.authorized = true;
+            }
+            Ok(output)
         }
 
-        fn artifact(&self) -> Option<Self::Artifact> { self.state.remote_cert().cloned() }
+        fn artifact(&self) -> Option<Self::Artifact> {
+            self.state.remote_cert().filter(|_| self.authorized).cloned()
+        }
+
+        fn error_kind(err: &Self::Error) -> HandshakeErrorKind {
+            match err {
+                eidolon::Error::InvalidCert
+                | eidolon::Error::SigMismatch
+                | eidolon::Error::Unauthorized(_) => HandshakeErrorKind::AuthRejected,
+                eidolon::Error::InvalidLen(_) | eidolon::Error::Completed => {
+                    HandshakeErrorKind::ProtocolViolation
+                }
+            }
+        }
 
         fn is_init(&self) -> bool { self.state.is_init() }
     }
@@ -608,7 +1061,9 @@ mod imp_eidolon {
     impl<S: NetSession, E: Ecdh, D: Digest> IntoInit<Vec<u8>>
         for ProtocolArtifact<NoiseState<E, D>, S>
     {
-        fn into_init(self) -> Vec<u8> { self.state.to_vec() }
+        // Remote static keys differ between the peers, so only the handshake
+        // hash (which already commits to them) is used.
+        fn into_init(self) -> Vec<u8> { self.state.handshake_hash.as_ref().to_vec() }
     }
 }
 #[cfg(feature = "eidolon")]
@@ -666,6 +1121,8 @@ mod impl_noise {
     }
 }
 
+pub use impl_noise::NoiseArtifact;
+
 mod impl_socks5 {
     use cyphernet::addr::Host;
     #[cfg(not(feature = "eidolon"))]
@@ -687,6 +1144,8 @@ mod impl_socks5 {
 
         fn advance(&mut self, input: &[u8]) -> Result<Vec<u8>, Self::Error> { self.advance(input) }
 
+        fn error_kind(_: &Self::Error) -> HandshakeErrorKind { HandshakeErrorKind::ProxyFailure }
+
         fn artifact(&self) -> Option<Self::Artifact> {
             match self {
                 Socks5::Initial(addr, false) if !addr.requires_proxy() => Some(addr.clone()),
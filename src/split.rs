@@ -21,6 +21,7 @@
 
 use std::io;
 use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
 
 use crate::connection::AsConnection;
 use crate::{NetConnection, NetSession, NetStateMachine};
@@ -76,8 +77,8 @@ impl<M: NetStateMachine, S: NetSession> io::Write for NetWriter<M, S> {
 }
 
 pub struct TcpReader<C: NetConnection> {
-    unique_id: u64,
-    connection: C,
+    pub(crate) unique_id: u64,
+    pub(crate) connection: C,
 }
 
 impl<C: NetConnection> io::Read for TcpReader<C> {
@@ -90,8 +91,8 @@ impl<C: NetConnection> AsConnection for TcpReader<C> {
 }
 
 pub struct TcpWriter<C: NetConnection> {
-    unique_id: u64,
-    connection: C,
+    pub(crate) unique_id: u64,
+    pub(crate) connection: C,
 }
 
 impl<C: NetConnection> io::Write for TcpWriter<C> {
@@ -105,37 +106,67 @@ impl<C: NetConnection> AsConnection for TcpWriter<C> {
     fn as_connection(&self) -> &Self::Connection { &self.connection }
 }
 
+/// Splits a connection into a [`TcpReader`] and a [`TcpWriter`] by cloning
+/// its file descriptor. Used to implement [`SplitIo::split_io`] for
+/// connections which can be cloned with [`NetConnection::try_clone`].
+pub(crate) fn split_cloned<C>(
+    connection: C,
+) -> Result<(TcpReader<C>, TcpWriter<C>), SplitIoError<C>>
+where C: NetConnection + SplitIo {
+    match connection.try_clone() {
+        Ok(clone) => {
+            let unique_id = rand::random();
+            let reader = TcpReader {
+                unique_id,
+                connection: clone,
+            };
+            let writer = TcpWriter {
+                unique_id,
+                connection,
+            };
+            Ok((reader, writer))
+        }
+        Err(error) => Err(SplitIoError {
+            original: connection,
+            error,
+        }),
+    }
+}
+
+/// Joins a connection split with [`split_cloned`]. Used to implement
+/// [`SplitIo::from_split_io`].
+///
+/// # Panics
+///
+/// If the reader and the writer were not produced by the same
+/// [`split_cloned`] call.
+pub(crate) fn join_cloned<C: NetConnection>(read: TcpReader<C>, write: TcpWriter<C>) -> C {
+    if read.unique_id != write.unique_id {
+        panic!("joining connections which were not produced by the same split_io()")
+    }
+    write.connection
+}
+
 impl SplitIo for TcpStream {
     type Read = TcpReader<Self>;
     type Write = TcpWriter<Self>;
 
     fn split_io(self) -> Result<(Self::Read, Self::Write), SplitIoError<Self>> {
-        match self.try_clone() {
-            Ok(clone) => {
-                let unique_id = rand::random();
-                let reader = TcpReader {
-                    unique_id,
-                    connection: clone,
-                };
-                let writer = TcpWriter {
-                    unique_id,
-                    connection: self,
-                };
-                Ok((reader, writer))
-            }
-            Err(error) => Err(SplitIoError {
-                original: self,
-                error,
-            }),
-        }
+        split_cloned(self)
     }
 
-    fn from_split_io(read: Self::Read, write: Self::Write) -> Self {
-        if read.unique_id != write.unique_id {
-            panic!("joining TcpStreams which were not produced by the same split_io()")
-        }
-        write.connection
+    fn from_split_io(read: Self::Read, write: Self::Write) -> Self { join_cloned(read, write) }
+}
+
+impl SplitIo for UnixStream {
+    type Read = TcpReader<Self>;
+    type Write = TcpWriter<Self>;
+
+    fn split_io(self) -> Result<(Self::Read, Self::Write), SplitIoError<Self>> {
+        split_cloned(self)
     }
+
+    fn from_split_io(read: Self::Read, write: Self::Write) -> Self { join_cloned(read, write) }
 }
 
 #[cfg(feature = "nonblocking")]
@@ -144,30 +175,8 @@ impl SplitIo for socket2::Socket {
     type Write = TcpWriter<Self>;
 
     fn split_io(self) -> Result<(Self::Read, Self::Write), SplitIoError<Self>> {
-        match self.try_clone() {
-            Ok(clone) => {
-                let unique_id = rand::random();
-                let reader = TcpReader {
-                    unique_id,
-                    connection: clone,
-                };
-                let writer = TcpWriter {
-                    unique_id,
-                    connection: self,
-                };
-                Ok((reader, writer))
-            }
-            Err(error) => Err(SplitIoError {
-                original: self,
-                error,
-            }),
-        }
+        split_cloned(self)
     }
 
-    fn from_split_io(read: Self::Read, write: Self::Write) -> Self {
-        if read.unique_id != write.unique_id {
-            panic!("joining TcpStreams which were not produced by the same split_io()")
-        }
-        write.connection
-    }
+    fn from_split_io(read: Self::Read, write: Self::Write) -> Self { join_cloned(read, write) }
 }
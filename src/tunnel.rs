@@ -19,15 +19,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
 use std::os::unix::io::AsRawFd;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io, net};
 
 use reactor::poller::{IoFail, IoType, Poll};
+use reactor::{Io, Resource, ResourceId, WriteAtomic};
 
-use crate::{NetConnection, NetSession, READ_BUFFER_SIZE};
+use crate::resource::{TransportState, EXHAUSTION_BACKOFF};
+use crate::{
+    Direction, GracefulClose, ListenerEvent, NetAccept, NetConnection, NetListener, NetSession,
+    NetTransport, SessionEvent, READ_BUFFER_SIZE,
+};
+
+/// Maximum number of bytes which [`MultiTunnel`] and [`ReverseTunnel`] buffer
+/// for a single side of a client connection. Once the limit is reached, the
+/// tunnel stops reading from the opposite side until the buffered data are
+/// written out.
+pub const SPLICE_BUFFER_LIMIT: usize = 4 * READ_BUFFER_SIZE;
 
 pub struct Tunnel<S: NetSession> {
     listener: net::TcpListener,
@@ -191,3 +202,937 @@ impl<S: NetSession> Tunnel<S> {
 
     pub fn into_session(self) -> S { self.session }
 }
+
+/// Statistics of a client connection served by a [`MultiTunnel`] or
+/// [`ReverseTunnel`], reported once the client connection is closed.
+#[derive(Debug)]
+pub struct TunnelReport<A = net::SocketAddr> {
+    /// Address of the client: a local client for [`MultiTunnel`] or a remote
+    /// peer for [`ReverseTunnel`].
+    pub client: A,
+    /// Number of bytes received from the remote peer and passed to the client.
+    pub received: usize,
+    /// Number of bytes received from the client and sent to the remote peer.
+    pub sent: usize,
+    /// Error which has terminated the connection, or `None` if one of the
+    /// sides has closed the connection.
+    pub error: Option<io::Error>,
+}
+
+/// Tunnel accepting multiple local clients and forwarding each of them through
+/// a dedicated [`NetSession`], which is created by a factory closure.
+///
+/// Unlike [`Tunnel`], which serves a single client, all clients and their
+/// sessions are served concurrently using a single poller.
+pub struct MultiTunnel<S: NetSession, F: FnMut(net::SocketAddr) -> io::Result<S>> {
+    listener: net::TcpListener,
+    factory: F,
+}
+
+impl<S: NetSession, F: FnMut(net::SocketAddr) -> io::Result<S>> MultiTunnel<S, F> {
+    /// Binds the tunnel to a local address. The `factory` closure is called
+    /// for each of the accepted clients with the client address and must
+    /// return a session with the remote peer; since it is called from the
+    /// poller thread it should not block for long.
+    pub fn with(factory: F, addr: impl net::ToSocketAddrs) -> io::Result<Self> {
+        let listener = net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, factory })
+    }
+
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> { self.listener.local_addr() }
+
+    /// Runs the tunnel, serving clients until the listener or poller fails.
+    ///
+    /// Client connections which had no I/O activity for the `timeout` are
+    /// closed with [`io::ErrorKind::TimedOut`] error. Once a client connection
+    /// is closed, its statistics are reported via the `report` callback.
+    ///
+    /// Up to [`SPLICE_BUFFER_LIMIT`] bytes are buffered in each direction; a
+    /// side which can't keep up with the other one stops being read from.
+    ///
+    /// Failures to accept a single connection (like connections aborted by
+    /// the client before being accepted) are logged and skipped. If the
+    /// process runs out of file descriptors, the tunnel stops accepting new
+    /// clients for [`EXHAUSTION_BACKOFF`] period, continuing to serve the
+    /// existing ones.
+    #[allow(unused_variables)]
+    pub fn run<P: Poll>(
+        &mut self,
+        mut poller: P,
+        timeout: Duration,
+        mut report: impl FnMut(TunnelReport),
+    ) -> io::Result<()> {
+        let listener_addr = self.listener.local_addr().expect("listener always has local addr");
+        #[cfg(feature = "log")]
+        log::info!(target: "tunnel", "Tunnel accepting multiple connections will run on {listener_addr}");
+
+        let listener_id = poller.register(&self.listener, IoType::read_only());
+        let mut splices = HashMap::<net::SocketAddr, Splice<S>>::new();
+        let mut resources = HashMap::<ResourceId, (net::SocketAddr, bool)>::new();
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        let mut paused_until = None::<Instant>;
+
+        loop {
+            let poll_timeout = paused_until.map_or(timeout, |until| {
+                until.saturating_duration_since(Instant::now()).min(timeout)
+            });
+            poller.poll(Some(poll_timeout))?;
+            if matches!(paused_until, Some(until) if Instant::now() >= until) {
+                #[cfg(feature = "log")]
+                log::debug!(target: "tunnel", "Tunnel {listener_addr} resumes accepting connections");
+                paused_until = None;
+                poller.set_interest(listener_id, IoType::read_only());
+            }
+            let events = (&mut poller).collect::<Vec<_>>();
+            for (id, res) in events {
+                if id == listener_id {
+                    match res {
+                        Ok(_) => {}
+                        Err(err) => return Err(io::Error::new(io::ErrorKind::BrokenPipe, err)),
+                    }
+                    loop {
+                        let (stream, client) = match self.listener.accept() {
+                            Ok(conn) => conn,
+                            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(err)
+                                if matches!(
+                                    err.raw_os_error(),
+                                    Some(libc::EMFILE | libc::ENFILE)
+                                ) =>
+                            {
+                                #[cfg(feature = "log")]
+                                log::warn!(target: "tunnel", "Out of file descriptors ({err}), tunnel {listener_addr} pauses accepting connections for {EXHAUSTION_BACKOFF:?}");
+                                paused_until = Some(Instant::now() + EXHAUSTION_BACKOFF);
+                                poller.set_interest(listener_id, IoType::none());
+                                break;
+                            }
+                            Err(err)
+                                if matches!(
+                                    err.kind(),
+                                    io::ErrorKind::ConnectionAborted
+                                        | io::ErrorKind::ConnectionReset
+                                        | io::ErrorKind::Interrupted
+                                ) =>
+                            {
+                                #[cfg(feature = "log")]
+                                log::debug!(target: "tunnel", "Unable to accept connection on tunnel {listener_addr}: {err}");
+                                continue;
+                            }
+                            Err(err) => return Err(err),
+                        };
+                        #[cfg(feature = "log")]
+                        log::debug!(target: "tunnel", "Incoming connection from {client} for tunnel {listener_addr}");
+
+                        let splice = (self.factory)(client).and_then(|session| {
+                            Splice::with(client, stream, session, timeout, &mut poller)
+                        });
+                        match splice {
+                            Ok(splice) => {
+                                resources.insert(splice.local_id, (client, true));
+                                resources.insert(splice.remote_id, (client, false));
+                                splices.insert(client, splice);
+                            }
+                            Err(err) => {
+                                #[cfg(feature = "log")]
+                                log::error!(target: "tunnel", "Unable to establish session for {client}: {err}");
+                                report(TunnelReport {
+                                    client,
+                                    received: 0,
+                                    sent: 0,
+                                    error: Some(err),
+                                });
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                let Some((client, is_local)) = resources.get(&id).copied() else {
+                    continue;
+                };
+                let Some(splice) = splices.get_mut(&client) else {
+                    continue;
+                };
+                let res = match res {
+                    Ok(ev) => splice.handle_io(is_local, ev, &mut poller, &mut buf),
+                    Err(IoFail::Connectivity(_)) => Ok(false),
+                    Err(err @ IoFail::Os(_)) => Err(io::Error::new(io::ErrorKind::BrokenPipe, err)),
+                };
+                match res {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        let splice = splices.remove(&client).expect("splice is present");
+                        report(splice.close(&mut poller, &mut resources, None));
+                    }
+                    Err(err) => {
+                        let splice = splices.remove(&client).expect("splice is present");
+                        report(splice.close(&mut poller, &mut resources, Some(err)));
+                    }
+                }
+            }
+
+            let expired = splices
+                .iter()
+                .filter(|(_, splice)| splice.last_activity.elapsed() >= timeout)
+                .map(|(client, _)| *client)
+                .collect::<Vec<_>>();
+            for client in expired {
+                #[cfg(feature = "log")]
+                log::warn!(target: "tunnel", "Tunnel {listener_addr} timed out with client {client}");
+
+                let splice = splices.remove(&client).expect("splice is present");
+                let err = io::ErrorKind::TimedOut.into();
+                report(splice.close(&mut poller, &mut resources, Some(err)));
+            }
+        }
+    }
+}
+
+/// Checks whether the listener accept error is caused by the listener itself
+/// rather than by a single incoming connection, such that retrying is useless.
+fn is_listener_broken(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EBADF | libc::EINVAL | libc::ENOTSOCK | libc::EOPNOTSUPP | libc::EFAULT)
+    )
+}
+
+/// Pair of a local TCP connection and a session with the remote peer, which
+/// data are pumped into each other.
+struct Splice<S: NetSession> {
+    client: net::SocketAddr,
+    stream: net::TcpStream,
+    session: S,
+    local_id: ResourceId,
+    remote_id: ResourceId,
+    in_buf: VecDeque<u8>,
+    out_buf: VecDeque<u8>,
+    received: usize,
+    sent: usize,
+    last_activity: Instant,
+}
+
+impl<S: NetSession> Splice<S> {
+    fn with(
+        client: net::SocketAddr,
+        stream: net::TcpStream,
+        mut session: S,
+        timeout: Duration,
+        poller: &mut impl Poll,
+    ) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let conn = session.as_connection_mut();
+        conn.set_nonblocking(true)?;
+        conn.set_read_timeout(Some(timeout))?;
+        conn.set_write_timeout(Some(timeout))?;
+
+        let local_id = poller.register(&stream, IoType::read_only());
+        let remote_id = poller.register(&session.as_connection().as_raw_fd(), IoType::read_only());
+
+        Ok(Self {
+            client,
+            stream,
+            session,
+            local_id,
+            remote_id,
+            in_buf: empty!(),
+            out_buf: empty!(),
+            received: 0,
+            sent: 0,
+            last_activity: Instant::now(),
+        })
+    }
+
+    /// # Returns
+    ///
+    /// `false` if one of the sides has closed the connection.
+    fn handle_io(
+        &mut self,
+        is_local: bool,
+        ev: IoType,
+        poller: &mut impl Poll,
+        buf: &mut [u8],
+    ) -> io::Result<bool> {
+        self.last_activity = Instant::now();
+        if ev.write {
+            let (writer, queue, count): (&mut dyn Write, _, _) = if is_local {
+                (&mut self.stream, &mut self.in_buf, &mut self.received)
+            } else {
+                (&mut self.session, &mut self.out_buf, &mut self.sent)
+            };
+            match writer.write(queue.make_contiguous()) {
+                Ok(0) => return Ok(false),
+                Ok(written) => {
+                    writer.flush()?;
+                    queue.drain(..written);
+                    *count += written;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if ev.read {
+            let (reader, queue): (&mut dyn Read, _) = if is_local {
+                (&mut self.stream, &mut self.out_buf)
+            } else {
+                (&mut self.session, &mut self.in_buf)
+            };
+            match reader.read(buf) {
+                Ok(0) => return Ok(false),
+                Ok(read) => queue.extend(&buf[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        poller.set_interest(self.local_id, IoType {
+            read: self.out_buf.len() < SPLICE_BUFFER_LIMIT,
+            write: !self.in_buf.is_empty(),
+        });
+        poller.set_interest(self.remote_id, IoType {
+            read: self.in_buf.len() < SPLICE_BUFFER_LIMIT,
+            write: !self.out_buf.is_empty(),
+        });
+        Ok(true)
+    }
+
+    fn close(
+        self,
+        poller: &mut impl Poll,
+        resources: &mut HashMap<ResourceId, (net::SocketAddr, bool)>,
+        error: Option<io::Error>,
+    ) -> TunnelReport {
+        #[cfg(feature = "log")]
+        match &error {
+            None => log::info!(target: "tunnel",
+                "Tunnel for {} has completed its work. Total {} bytes are received and {} sent",
+                self.client, self.received, self.sent
+            ),
+            Some(err) => {
+                log::error!(target: "tunnel", "Tunnel for {} has terminated with '{err}'", self.client)
+            }
+        }
+
+        poller.unregister(self.local_id);
+        poller.unregister(self.remote_id);
+        resources.remove(&self.local_id);
+        resources.remove(&self.remote_id);
+        TunnelReport {
+            client: self.client,
+            received: self.received,
+            sent: self.sent,
+            error,
+        }
+    }
+}
+
+/// Reverse tunnel, accepting incoming sessions from remote peers and forwarding
+/// each of them to a fixed local service.
+///
+/// Connections are accepted with [`NetAccept`] and converted into sessions by
+/// a factory closure. Each session is run as a [`NetTransport`], such that the
+/// session handshake is performed before the connection to the local service
+/// is opened. All sessions are served concurrently using a single poller.
+pub struct ReverseTunnel<S, F, L = net::TcpListener>
+where
+    S: NetSession,
+    L: NetListener<Stream = S::Connection>,
+    F: FnMut(S::Connection) -> io::Result<S>,
+{
+    accept: NetAccept<S, L>,
+    service: net::SocketAddr,
+    factory: F,
+}
+
+impl<S, F, L> ReverseTunnel<S, F, L>
+where
+    S: NetSession,
+    L: NetListener<Stream = S::Connection>,
+    F: FnMut(S::Connection) -> io::Result<S>,
+{
+    /// Constructs tunnel forwarding sessions accepted by the `accept` listener
+    /// to the local `service`. The `factory` closure is called for each of the
+    /// accepted connections and must return a session, whose handshake will
+    /// be run by the tunnel.
+    pub fn with(accept: NetAccept<S, L>, service: net::SocketAddr, factory: F) -> Self {
+        Self {
+            accept,
+            service,
+            factory,
+        }
+    }
+
+    pub fn local_addr(&self) -> L::Addr { self.accept.local_addr() }
+
+    pub fn service_addr(&self) -> net::SocketAddr { self.service }
+
+    /// Runs the tunnel, serving remote peers until the listener or poller
+    /// fails.
+    ///
+    /// Sessions which had no I/O activity for the `timeout` are closed with
+    /// [`io::ErrorKind::TimedOut`] error; the same timeout is used for
+    /// connecting to the local service, which is done in a non-blocking way.
+    /// Once the local service closes its connection, the session is closed
+    /// gracefully with [`NetTransport::close`]. Once a session is closed, its
+    /// statistics are reported via the `report` callback.
+    ///
+    /// The local service is not read from while the session transport is
+    /// congested (see [`SessionEvent::Congested`]), and the session is not read
+    /// from while more than [`SPLICE_BUFFER_LIMIT`] bytes wait to be written
+    /// to the local service.
+    ///
+    /// Failures to accept or to set up a single connection are logged and
+    /// skipped; only errors indicating that the listener itself is broken stop
+    /// the tunnel.
+    #[allow(unused_variables)]
+    pub fn run<P: Poll>(
+        &mut self,
+        mut poller: P,
+        timeout: Duration,
+        mut report: impl FnMut(TunnelReport<<S::Connection as NetConnection>::Addr>),
+    ) -> io::Result<()> {
+        let service = self.service;
+        #[cfg(feature = "log")]
+        log::info!(target: "tunnel", "Reverse tunnel to {service} will run on {}", self.accept.local_addr());
+
+        let listener_id = poller.register(&self.accept, IoType::read_only());
+        let mut splices = HashMap::<ResourceId, ReverseSplice<S>>::new();
+        let mut locals = HashMap::<ResourceId, ResourceId>::new();
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+
+        loop {
+            let poll_timeout = self.accept.paused_for().map_or(timeout, |pause| pause.min(timeout));
+            poller.poll(Some(poll_timeout))?;
+            let events = (&mut poller).collect::<Vec<_>>();
+            for (id, res) in events {
+                if id == listener_id {
+                    if let Err(err) = res {
+                        return Err(io::Error::new(io::ErrorKind::BrokenPipe, err));
+                    }
+                    let connection = match self.accept.handle_io(Io::Read) {
+                        Some(ListenerEvent::Accepted(connection)) => connection,
+                        Some(ListenerEvent::Failure(err))
+                            if err.kind() == io::ErrorKind::WouldBlock =>
+                        {
+                            continue
+                        }
+                        Some(ListenerEvent::Failure(err)) if is_listener_broken(&err) => {
+                            return Err(err)
+                        }
+                        Some(ListenerEvent::Failure(err)) => {
+                            #[cfg(feature = "log")]
+                            log::warn!(target: "tunnel", "Unable to accept incoming connection: {err}");
+                            continue;
+                        }
+                        #[allow(unused_variables)]
+                        Some(ListenerEvent::Rejected(peer, reason)) => {
+                            #[cfg(feature = "log")]
+                            log::debug!(target: "tunnel", "Rejected incoming connection from {peer}: {reason}");
+                            continue;
+                        }
+                        // The listener pauses itself; its interests are updated below
+                        Some(ListenerEvent::Exhausted(..)) => continue,
+                        None => continue,
+                    };
+                    let peer = match connection.remote_addr() {
+                        Ok(peer) => peer,
+                        Err(err) => {
+                            #[cfg(feature = "log")]
+                            log::warn!(target: "tunnel", "Dropping incoming connection with unknown address: {err}");
+                            continue;
+                        }
+                    };
+                    #[cfg(feature = "log")]
+                    log::debug!(target: "tunnel", "Incoming connection from {peer} for tunnel to {service}");
+
+                    let config = self.accept.config();
+                    let splice = (self.factory)(connection)
+                        .and_then(|session| {
+                            NetTransport::with_session_and_config(
+                                session,
+                                Direction::Inbound,
+                                config,
+                            )
+                        })
+                        .and_then(|transport| {
+                            ReverseSplice::with(peer.clone(), transport, service, timeout)
+                        });
+                    match splice {
+                        Ok(mut splice) => {
+                            let transport_id =
+                                poller.register(&splice.transport, splice.transport_interests());
+                            splice.update(transport_id, &mut poller, &mut locals);
+                            splices.insert(transport_id, splice);
+                        }
+                        Err(err) => {
+                            #[cfg(feature = "log")]
+                            log::error!(target: "tunnel", "Unable to establish session for {peer}: {err}");
+                            report(TunnelReport {
+                                client: peer,
+                                received: 0,
+                                sent: 0,
+                                error: Some(err),
+                            });
+                        }
+                    }
+                    continue;
+                }
+
+                let (transport_id, is_local) = match locals.get(&id) {
+                    Some(transport_id) => (*transport_id, true),
+                    None => (id, false),
+                };
+                let Some(splice) = splices.get_mut(&transport_id) else {
+                    continue;
+                };
+                let res = match res {
+                    Ok(ev) if is_local => splice.handle_local(ev, &mut buf),
+                    Ok(ev) => splice.handle_remote(ev, service, timeout),
+                    Err(IoFail::Connectivity(_)) if is_local && splice.is_connecting() => {
+                        Err(splice.connect_error())
+                    }
+                    Err(IoFail::Connectivity(_)) if is_local => {
+                        splice.close_local();
+                        Ok(true)
+                    }
+                    Err(IoFail::Connectivity(_)) => Ok(false),
+                    Err(err @ IoFail::Os(_)) => Err(io::Error::new(io::ErrorKind::BrokenPipe, err)),
+                };
+                let res = res.map(|active| {
+                    splice.update(transport_id, &mut poller, &mut locals);
+                    active
+                });
+                let error = match res {
+                    Ok(true) => continue,
+                    Ok(false) => None,
+                    Err(err) => Some(err),
+                };
+                let splice = splices.remove(&transport_id).expect("splice is present");
+                report(splice.finish(transport_id, &mut poller, &mut locals, error));
+            }
+
+            let expired = splices
+                .iter()
+                .filter(|(_, splice)| {
+                    splice.last_activity.elapsed() >= timeout || splice.is_connect_expired()
+                })
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            for transport_id in expired {
+                let splice = splices.remove(&transport_id).expect("splice is present");
+                #[cfg(feature = "log")]
+                log::warn!(target: "tunnel", "Reverse tunnel to {service} timed out with {}", splice.peer);
+
+                let err = io::ErrorKind::TimedOut.into();
+                report(splice.finish(transport_id, &mut poller, &mut locals, Some(err)));
+            }
+            // Transport interests may change with time (for instance, due to
+            // the handshake timeout), so we re-evaluate them after each poll.
+            for (transport_id, splice) in &mut splices {
+                poller.set_interest(*transport_id, splice.transport_interests());
+            }
+            poller.set_interest(listener_id, self.accept.interests());
+        }
+    }
+
+    pub fn into_accept(self) -> NetAccept<S, L> { self.accept }
+}
+
+/// Session with a remote peer and a connection to the local service, which
+/// data are pumped into each other.
+struct ReverseSplice<S: NetSession> {
+    peer: <S::Connection as NetConnection>::Addr,
+    transport: NetTransport<S>,
+    local: Option<net::TcpStream>,
+    local_id: Option<ResourceId>,
+    /// Deadline for the connection to the local service, which is set while
+    /// the connection is in progress.
+    connect_deadline: Option<Instant>,
+    local_buf: VecDeque<u8>,
+    received: usize,
+    sent: usize,
+    last_activity: Instant,
+}
+
+impl<S: NetSession> ReverseSplice<S> {
+    fn with(
+        peer: <S::Connection as NetConnection>::Addr,
+        transport: NetTransport<S>,
+        service: net::SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let mut splice = Self {
+            peer,
+            transport,
+            local: None,
+            local_id: None,
+            connect_deadline: None,
+            local_buf: empty!(),
+            received: 0,
+            sent: 0,
+            last_activity: Instant::now(),
+        };
+        // Sessions without a handshake are active from the start
+        if splice.transport.state() == TransportState::Active {
+            splice.connect_local(service, timeout)?;
+        }
+        Ok(splice)
+    }
+
+    fn connect_local(&mut self, service: net::SocketAddr, timeout: Duration) -> io::Result<()> {
+        #[cfg(feature = "log")]
+        log::debug!(target: "tunnel", "Session with {} is established, connecting to {service}", self.peer);
+
+        let stream = net::TcpStream::connect_nonblocking(service.into(), timeout)?;
+        self.local = Some(stream);
+        self.connect_deadline = Some(Instant::now() + timeout);
+        Ok(())
+    }
+
+    fn is_connecting(&self) -> bool { self.connect_deadline.is_some() }
+
+    fn is_connect_expired(&self) -> bool {
+        matches!(self.connect_deadline, Some(deadline) if Instant::now() >= deadline)
+    }
+
+    /// Returns the reason of the failed connection to the local service.
+    fn connect_error(&self) -> io::Error {
+        match self.local.as_ref().map(net::TcpStream::take_error) {
+            Some(Ok(Some(err))) | Some(Err(err)) => err,
+            Some(Ok(None)) | None => io::ErrorKind::ConnectionRefused.into(),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// `false` if the session was closed.
+    fn handle_remote(
+        &mut self,
+        ev: IoType,
+        service: net::SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<bool> {
+        self.last_activity = Instant::now();
+        for io in [Io::Read, Io::Write] {
+            if (io == Io::Read && !ev.read) || (io == Io::Write && !ev.write) {
+                continue;
+            }
+            if self.transport.state() == TransportState::Terminated {
+                return Ok(false);
+            }
+            match self.transport.handle_io(io) {
+                Some(SessionEvent::Established(..)) => self.connect_local(service, timeout)?,
+                Some(SessionEvent::Data(data)) => self.local_buf.extend(data),
+                Some(SessionEvent::Terminated(err))
+                    if err.kind() == io::ErrorKind::ConnectionReset
+                        || err
+                            .get_ref()
+                            .map(|err| err.is::<GracefulClose>())
+                            .unwrap_or_default() =>
+                {
+                    return Ok(false)
+                }
+                Some(SessionEvent::Terminated(err)) | Some(SessionEvent::ConnectionFailed(err)) => {
+                    return Err(err)
+                }
+                Some(SessionEvent::HandshakeTimeout) => return Err(io::ErrorKind::TimedOut.into()),
+                // Reads from the local service are paused while the transport
+                // is congested; the interests are updated by `update`
+                Some(SessionEvent::Congested) | Some(SessionEvent::Drained) => {}
+                Some(SessionEvent::Idle(_)) | None => {}
+                Some(SessionEvent::Frame(never)) => match never {},
+            }
+        }
+        Ok(true)
+    }
+
+    /// # Returns
+    ///
+    /// `false` if the session was closed.
+    fn handle_local(&mut self, ev: IoType, buf: &mut [u8]) -> io::Result<bool> {
+        self.last_activity = Instant::now();
+        if self.is_connecting() {
+            if let Some(Ok(Some(err)) | Err(err)) =
+                self.local.as_ref().map(net::TcpStream::take_error)
+            {
+                return Err(err);
+            }
+            #[cfg(feature = "log")]
+            log::debug!(target: "tunnel", "Connected to the local service for {}", self.peer);
+
+            self.connect_deadline = None;
+            return Ok(true);
+        }
+        let Some(stream) = &mut self.local else {
+            return Ok(true);
+        };
+        if ev.write {
+            match stream.write(self.local_buf.make_contiguous()) {
+                Ok(0) => {
+                    self.close_local();
+                    return Ok(true);
+                }
+                Ok(written) => {
+                    self.local_buf.drain(..written);
+                    self.received += written;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if ev.read && !self.transport.is_congested() {
+            match stream.read(buf) {
+                Ok(0) => self.close_local(),
+                Ok(read) => {
+                    self.transport.write_atomic(&buf[..read]).map_err(|err| match err {
+                        reactor::WriteError::NotReady => io::ErrorKind::NotConnected.into(),
+                        reactor::WriteError::Io(err) => err,
+                    })?;
+                    self.sent += read;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Drops connection to the local service and starts graceful closing of
+    /// the session.
+    fn close_local(&mut self) {
+        #[cfg(feature = "log")]
+        log::debug!(target: "tunnel", "Local service has closed connection for {}", self.peer);
+
+        // The stream itself is dropped and unregistered from the poller by
+        // `update`, once the transport is in the closing state
+        self.local_buf.clear();
+        self.transport.close();
+    }
+
+    /// Interests of the transport, which do not include reading while data
+    /// for the local service are above [`SPLICE_BUFFER_LIMIT`].
+    fn transport_interests(&self) -> IoType {
+        let mut interests = self.transport.interests();
+        if self.local_buf.len() >= SPLICE_BUFFER_LIMIT {
+            interests.read = false;
+        }
+        interests
+    }
+
+    /// Synchronizes poller registrations and interests with the splice state.
+    fn update(
+        &mut self,
+        transport_id: ResourceId,
+        poller: &mut impl Poll,
+        locals: &mut HashMap<ResourceId, ResourceId>,
+    ) {
+        poller.set_interest(transport_id, self.transport_interests());
+        if self.transport.state() == TransportState::Closing {
+            if let Some(id) = self.local_id.take() {
+                poller.unregister(id);
+                locals.remove(&id);
+            }
+            self.local = None;
+            return;
+        }
+        let Some(stream) = &self.local else {
+            return;
+        };
+        let id = *self.local_id.get_or_insert_with(|| {
+            let id = poller.register(stream, IoType::read_only());
+            locals.insert(id, transport_id);
+            id
+        });
+        let interest = if self.is_connecting() {
+            IoType::write_only()
+        } else {
+            IoType {
+                read: !self.transport.is_congested(),
+                write: !self.local_buf.is_empty(),
+            }
+        };
+        poller.set_interest(id, interest);
+    }
+
+    fn finish(
+        self,
+        transport_id: ResourceId,
+        poller: &mut impl Poll,
+        locals: &mut HashMap<ResourceId, ResourceId>,
+        error: Option<io::Error>,
+    ) -> TunnelReport<<S::Connection as NetConnection>::Addr> {
+        #[cfg(feature = "log")]
+        match &error {
+            None => log::info!(target: "tunnel",
+                "Reverse tunnel for {} has completed its work. Total {} bytes are received and {} sent",
+                self.peer, self.received, self.sent
+            ),
+            Some(err) => {
+                log::error!(target: "tunnel", "Reverse tunnel for {} has terminated with '{err}'", self.peer)
+            }
+        }
+
+        poller.unregister(transport_id);
+        if let Some(id) = self.local_id {
+            poller.unregister(id);
+            locals.remove(&id);
+        }
+        TunnelReport {
+            client: self.peer,
+            received: self.received,
+            sent: self.sent,
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use reactor::poller::popol;
+
+    use super::*;
+    use crate::Loopback;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Sends back everything it reads until the stream is closed.
+    fn echo(mut stream: impl Read + Write) {
+        let mut buf = [0u8; 1024];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(read) => {
+                    if stream.write_all(&buf[..read]).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes `msg` through the tunnel and reads back its echo.
+    fn roundtrip(stream: &mut net::TcpStream, msg: &[u8]) {
+        stream.set_read_timeout(Some(TIMEOUT * 5)).unwrap();
+        stream.write_all(msg).unwrap();
+        let mut buf = vec![0u8; msg.len()];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, msg);
+    }
+
+    #[test]
+    fn multi_tunnel() {
+        let (remote_tx, remote_rx) = mpsc::channel::<Loopback>();
+        thread::spawn(move || {
+            for remote in remote_rx {
+                thread::spawn(move || echo(remote));
+            }
+        });
+        let factory = move |_| {
+            let (session, remote) = Loopback::pair()?;
+            remote_tx.send(remote).expect("remote side is running");
+            Ok(session)
+        };
+        let mut tunnel = MultiTunnel::with(factory, "127.0.0.1:0").unwrap();
+        let addr = tunnel.local_addr().unwrap();
+        let (report_tx, report_rx) = mpsc::channel();
+        thread::spawn(move || {
+            tunnel.run(popol::Poller::new(), TIMEOUT, |report| {
+                let _ = report_tx.send(report);
+            })
+        });
+
+        // Both clients are connected at the same time
+        let mut first = net::TcpStream::connect(addr).unwrap();
+        let mut second = net::TcpStream::connect(addr).unwrap();
+        roundtrip(&mut first, b"hello");
+        roundtrip(&mut second, b"hello from the second client");
+        roundtrip(&mut first, b", world");
+        let first_addr = first.local_addr().unwrap();
+        let second_addr = second.local_addr().unwrap();
+        drop(second);
+        drop(first);
+
+        let mut reports =
+            (0..2).map(|_| report_rx.recv_timeout(TIMEOUT * 5).unwrap()).collect::<Vec<_>>();
+        reports.sort_by_key(|report| report.client != first_addr);
+        assert_eq!(reports[0].client, first_addr);
+        assert_eq!((reports[0].sent, reports[0].received), (12, 12));
+        assert!(reports[0].error.is_none());
+        assert_eq!(reports[1].client, second_addr);
+        assert_eq!((reports[1].sent, reports[1].received), (28, 28));
+        assert!(reports[1].error.is_none());
+
+        // Idle client is disconnected once the timeout expires
+        let idle = net::TcpStream::connect(addr).unwrap();
+        let report = report_rx.recv_timeout(TIMEOUT * 5).unwrap();
+        assert_eq!(report.client, idle.local_addr().unwrap());
+        assert_eq!((report.sent, report.received), (0, 0));
+        assert_eq!(report.error.unwrap().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn reverse_tunnel() {
+        // Local service echoing data, which closes the connection after `bye`
+        let service = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let service_addr = service.local_addr().unwrap();
+        thread::spawn(move || {
+            for mut stream in service.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut buf = [0u8; 1024];
+                    while let Ok(read @ 1..) = stream.read(&mut buf) {
+                        if stream.write_all(&buf[..read]).is_err() || &buf[..read] == b"bye" {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        let accept =
+            NetAccept::<net::TcpStream>::bind(&net::SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let mut tunnel = ReverseTunnel::with(accept, service_addr, Ok);
+        let addr = tunnel.local_addr();
+        let (report_tx, report_rx) = mpsc::channel();
+        thread::spawn(move || {
+            tunnel.run(popol::Poller::new(), TIMEOUT, |report| {
+                let _ = report_tx.send(report);
+            })
+        });
+
+        // Both peers are connected at the same time; the first one closes the
+        // connection itself, while the second one is closed by the service
+        let mut first = net::TcpStream::connect(addr).unwrap();
+        let mut second = net::TcpStream::connect(addr).unwrap();
+        roundtrip(&mut first, b"hello");
+        roundtrip(&mut second, b"hello from the second peer");
+        roundtrip(&mut first, b", world");
+        roundtrip(&mut second, b"bye");
+        assert_eq!(second.read(&mut [0u8; 16]).unwrap(), 0);
+        let first_port = first.local_addr().unwrap().port();
+        let second_port = second.local_addr().unwrap().port();
+        drop(first);
+
+        let mut reports =
+            (0..2).map(|_| report_rx.recv_timeout(TIMEOUT * 5).unwrap()).collect::<Vec<_>>();
+        reports.sort_by_key(|report| report.client.port != first_port);
+        assert_eq!(reports[0].client.port, first_port);
+        assert_eq!((reports[0].sent, reports[0].received), (12, 12));
+        assert!(reports[0].error.is_none());
+        assert_eq!(reports[1].client.port, second_port);
+        assert_eq!((reports[1].sent, reports[1].received), (29, 29));
+        assert!(reports[1].error.is_none());
+
+        // Idle peer is disconnected once the timeout expires
+        let idle = net::TcpStream::connect(addr).unwrap();
+        let report = report_rx.recv_timeout(TIMEOUT * 5).unwrap();
+        assert_eq!(report.client.port, idle.local_addr().unwrap().port());
+        assert_eq!((report.sent, report.received), (0, 0));
+        assert_eq!(report.error.unwrap().kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,60 @@
+// Library for building scalable privacy-preserving microservices P2P nodes
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2022-2023 by
+//     Dr. Maxim Orlovsky <orlovsky@cyphernet.org>
+//
+// Copyright 2022-2023 Cyphernet DAO, Switzerland
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Socket activation is configured with process-wide environment variables,
+// which can't be modified while other threads may read them; hence the test
+// runs in its own binary, and this must remain the only test in it.
+
+use std::env;
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+use netservices::activation::{listen_fds, LISTEN_FDS_START};
+
+#[test]
+fn activation() {
+    assert!(listen_fds().unwrap().is_empty());
+
+    // The listener is moved to the first descriptor passed by the service
+    // manager
+    assert!(unsafe { libc::fcntl(LISTEN_FDS_START, libc::F_GETFD) } < 0);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let raw = listener.into_raw_fd();
+    if raw != LISTEN_FDS_START {
+        assert_eq!(unsafe { libc::dup2(raw, LISTEN_FDS_START) }, LISTEN_FDS_START);
+        assert_eq!(unsafe { libc::close(raw) }, 0);
+    }
+
+    env::set_var("LISTEN_PID", std::process::id().to_string());
+    env::set_var("LISTEN_FDS", "1");
+    env::set_var("LISTEN_FDNAMES", "http");
+    let fds = listen_fds().unwrap();
+    assert_eq!(fds.len(), 1);
+    assert_eq!(fds[0].fd.as_raw_fd(), LISTEN_FDS_START);
+    assert_eq!(fds[0].name.as_deref(), Some("http"));
+    let adopted = TcpListener::from(fds.into_iter().next().unwrap().fd);
+    assert_eq!(adopted.local_addr().unwrap(), addr);
+
+    // The environment is left intact, but the descriptors are not taken twice
+    assert_eq!(env::var("LISTEN_FDS").as_deref(), Ok("1"));
+    assert!(listen_fds().unwrap().is_empty());
+}